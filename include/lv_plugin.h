@@ -0,0 +1,43 @@
+/* C interface of lv native plugins, load them with `lv FILE --plugin libname.so` */
+#ifndef LV_PLUGIN_H
+#define LV_PLUGIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define LV_PLUGIN_API_VERSION 1
+
+typedef struct lv_api lv_api;
+
+/* only the first field is public, the vm keeps its own state after it */
+typedef struct lv_ctx {
+    const lv_api *api;
+} lv_ctx;
+
+typedef struct lv_registrar {
+    const lv_api *api;
+} lv_registrar;
+
+/* returns 0, or the nonzero result of a failed api call to stop the program with its error */
+typedef int32_t (*lv_native)(lv_ctx *ctx, void *data);
+
+/* api calls return 0 or the negative code of the vm error (see ExecErr::code) */
+struct lv_api {
+    uint32_t version;
+    /* the native is called with data, the vm checks there are arity values on the stack
+       and that the program was granted capability; -1 for a name that isn't utf-8 */
+    int32_t (*register_native)(lv_registrar *reg, const char *name, const char *capability,
+                               size_t arity, lv_native func, void *data);
+    int32_t (*pop)(lv_ctx *ctx, intptr_t *out);
+    int32_t (*push)(lv_ctx *ctx, intptr_t value);
+    /* pointers are vm addresses, the same as read64/write64 use */
+    int32_t (*read_bytes)(lv_ctx *ctx, intptr_t ptr, uint8_t *buf, size_t len);
+    int32_t (*write_bytes)(lv_ctx *ctx, intptr_t ptr, const uint8_t *buf, size_t len);
+    /* text for the output of the program */
+    int32_t (*output)(lv_ctx *ctx, const uint8_t *s, size_t len);
+};
+
+/* the entry point every plugin exports, returns 0 when its natives are registered */
+int32_t lv_plugin_init(lv_registrar *reg);
+
+#endif
@@ -1,5 +1,5 @@
 use std::{process::ExitCode, io::{self, Write}};
-use lv::file::*;
+use lv::{file::*, InstType};
 
 fn main() -> ExitCode {
     let args: Vec<_> = std::env::args().collect();
@@ -17,27 +17,39 @@ fn main() -> ExitCode {
         }
     };
 
-    let mut i = 0;
-    while i < prog.mem.len() {
-        if i+8 <= prog.mem.len() {
-            println!("@mem{} {}", i, isize::from_ne_bytes(match prog.mem[i..i+8].try_into() {
-                Ok(v) => {v} Err(_) => {unreachable!()}
-            }));
-            i += 8;
+    println!("; guard {}", prog.guard);
+    let mut adr = prog.guard;
+    if !prog.rodata.is_empty() {
+        match asm_string(&prog.rodata) {
+            Some(s) => println!("@ro{adr} \"{s}\""),
+            None => {
+                println!("; read-only data with bytes a string can't hold, reassembles as writable data");
+                print_words("ro", adr, &prog.rodata);
+            }
         }
-        else {
-            let mut v: Vec<u8> = prog.mem[i..].to_vec();
-            v.append(&mut vec![0u8;i+8-prog.mem.len()]);
-            println!("@mem{} {}", i, isize::from_ne_bytes(match v[..].try_into() {
-                Ok(v) => {v} Err(_) => {unreachable!()}
-            }));
-            break;
-        };
+        adr += prog.rodata.len();
+    }
+
+    print_words("mem", adr, &prog.data);
+    adr += prog.data.len();
+    if prog.bss > 0 {
+        println!("@bss{} [{}]", adr, prog.bss);
+    }
+
+    for ext in &prog.externs {
+        println!("{ext}");
+    }
+    for (i, name) in prog.imports.iter().enumerate() {
+        println!("; import {i} {name}");
     }
 
     let mut prog_str: Vec<u8> = vec![];
     for inst in prog.inst {
-        prog_str.extend(inst.to_string().as_bytes());
+        let asm = match inst.kind {
+            InstType::NATIVE_IMPORT => format!("native {}", prog.imports.get(inst.operand as usize).map_or("?", |n| n)),
+            _ => inst.to_asm(),
+        };
+        prog_str.extend(asm.as_bytes());
         prog_str.push(b'\n');
     }
 
@@ -51,3 +63,30 @@ fn main() -> ExitCode {
 
     0.into()
 }
+
+// the bytes as a string literal using only the escapes asm_parse understands,
+// None if a byte would be read back differently (quotes, comments, backslashes, other control characters)
+fn asm_string(bytes: &[u8]) -> Option<String> {
+    let str = std::str::from_utf8(bytes).ok()?;
+    let mut out = String::new();
+    for c in str.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\0' => out.push_str("\\0"),
+            '"' | ';' | '#' | '\\' => return None,
+            c if c.is_control() => return None,
+            c => out.push(c),
+        }
+    }
+    Some(out)
+}
+
+// @<prefix><address> lines of 8 byte values, the last one padded with zeros
+fn print_words(prefix: &str, adr: usize, bytes: &[u8]) {
+    for (i, chunk) in bytes.chunks(8).enumerate() {
+        let mut v = [0u8; 8];
+        v[..chunk.len()].copy_from_slice(chunk);
+        println!("@{prefix}{} {}", adr+i*8, isize::from_ne_bytes(v));
+    }
+}
@@ -1,9 +1,11 @@
-use std::{process::ExitCode, io::stdin};
-use lv::{Lada, file::*, Inst, InstType, PrintType, ExecErr};
+use std::{process::ExitCode, io::{self, stdin, BufRead, BufReader}, fs::File};
+use lv::{Lada, file::*, files, input, random, plugin, ffi, native::Policy, host::StdHost, Inst, InstType, PrintType, ExecErr, Step, Limits, MemModel, Region, OverflowMode, sanitizer::SanitizeMode,
+    watch::{Watchpoint, WatchKind}};
 
 const HELP_PAGE: &str = "Lada Virtual machine
 
-Usage: lv FILE [OPTIONS]
+Usage: lv FILE [OPTIONS] [-- ARGS]
+  ARGS are passed to the program, it starts with argc and argv (FILE and ARGS) on the stack
   -h, --help\tprint this page
   -d\t\trun in debug mode
   -D\t\trun in step debug mode
@@ -14,7 +16,108 @@ Usage: lv FILE [OPTIONS]
   -b\t\tprint values (stack & arena) as hexadecimal
   -S\t\tdynamically growing stack
   -R\t\tdynamic arena resizing
-  -m\t\tprint dynamic memory";
+  -m\t\tprint dynamic memory
+  --linear\tallocate dynamic memory in the arena, pointers are plain offsets
+  --linear-stack\tlike --linear and the stack can be accessed through pointers too
+  --sanitize=memory\tstop on reads of uninitialized memory
+  --sanitize=memory-warn\twarn about reads of uninitialized memory
+  --overflow [m]\tinteger overflow behaviour: wrap (default), check or saturate
+  --watch [w]\tstop when memory is written, w is [cN:]ADR[+LEN] (cN for dynamic chunk N)
+  --watch-read [w]\tstop when memory is read
+  --watch-rw [w]\tstop when memory is read or written
+  --max-stack [n]\tlimit the stack to n values
+  --max-arena [n]\tlimit the arena to n bytes
+  --max-dyn [n]\tlimit allocated dynamic memory to n bytes
+  --max-chunks [n]\tlimit the number of live dynamic memory chunks
+  --max-output [n]\tlimit the program output to n bytes
+  --fs-root [dir]\tlet the program read files in dir with the file natives
+  --fs-root-rw [dir]\tlet the program read and write files in dir
+  --stdin [file]\tprogram input is read from file instead of stdin
+  --seed [n]\tseed of the random number natives, by default it's taken from the clock
+  --allow [caps]\tgrant the comma separated capabilities to the natives, e.g. io.stdout,time
+  --allow-all\tgrant every capability
+  --policy [file]\tgrant the capabilities listed in file, one per line
+  --plugin [lib]\tload the natives of a shared library, can be given more than once
+  --lib [lib]\tlook up %extern functions in a shared library before libc, can be given more than once
+  --audit\tlist the imported natives and their capabilities instead of running,
+\t\tfails if the policy doesn't grant one of them";
+
+fn parse_size(args: &[String], i: usize, what: &str) -> Option<usize> {
+    match args.get(i).map(|a| a.parse::<usize>()) {
+        Some(Ok(v)) => Some(v),
+        Some(Err(e)) => {
+            eprintln!("Error while parsing {what}: {e}");
+            None
+        }
+        None => {
+            eprintln!("Missing value for {what}");
+            None
+        }
+    }
+}
+
+fn parse_watch(spec: Option<&String>, kind: WatchKind) -> Option<Watchpoint> {
+    let Some(spec) = spec else {
+        eprintln!("Missing watchpoint");
+        return None;
+    };
+    let (region, rest) = match spec.strip_prefix('c').and_then(|s| s.split_once(':')) {
+        Some((n, rest)) => (Region::Chunk(n.parse().ok()?), rest),
+        None => (Region::Arena, spec.as_str()),
+    };
+    let (start, len) = match rest.split_once('+') {
+        Some((start, len)) => (start.parse().ok()?, len.parse().ok()?),
+        None => (rest.parse().ok()?, 8),
+    };
+    Some(Watchpoint { region, start, len, kind })
+}
+
+// step debugging reads from the terminal so the program keeps stdin
+fn wait_step() {
+    let mut s = String::new();
+    let res = match File::open("/dev/tty") {
+        Ok(tty) => BufReader::new(tty).read_line(&mut s),
+        Err(_) => io::stdin().read_line(&mut s),
+    };
+    if let Err(e) = res {
+        eprintln!("Error while waiting for the next step: {e}");
+    }
+}
+
+#[cfg(all(unix, target_arch = "x86_64"))]
+fn register_externs(vm: &mut Lada, libs: &[String]) -> io::Result<()> {
+    let externs = vm.externs().to_vec();
+    let libs: Vec<_> = libs.iter().map(std::path::Path::new).collect();
+    ffi::register(vm.natives_mut(), &externs, &libs)
+}
+
+#[cfg(not(all(unix, target_arch = "x86_64")))]
+fn register_externs(_: &mut Lada, _: &[String]) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "extern functions need x86-64 with the SysV ABI"))
+}
+
+// what the program can do with the natives, 1 if something it imports isn't available or granted
+fn audit_natives(vm: &Lada) -> u8 {
+    let mut status = 0;
+    for (i, name) in vm.imports().iter().enumerate() {
+        match vm.natives().get(name).map(|n| &vm.natives().natives()[n]) {
+            Some(def) if vm.policy().allows(&def.capability) => println!("import {i} {name}: {} granted", def.capability),
+            Some(def) => {
+                println!("import {i} {name}: {} denied", def.capability);
+                status = 1;
+            }
+            None => {
+                println!("import {i} {name}: not available");
+                status = 1;
+            }
+        }
+    }
+    // the index is only known at runtime, so any native could be called
+    if (0..vm.prog_len()).any(|n| vm.inst(n).kind == InstType::NATIVE) {
+        println!("the program calls natives by index, it can reach every native the policy grants");
+    }
+    status
+}
 
 fn main() -> ExitCode {
     let prog;
@@ -27,6 +130,20 @@ fn main() -> ExitCode {
     let mut arena_resize = false;
     let mut debug_mem = false;
     let mut print_type = PrintType::I64;
+    let mut limits = Limits::default();
+    let mut mem_model = MemModel::Split;
+    let mut sanitize = None;
+    let mut watchpoints = vec![];
+    let mut overflow = OverflowMode::Wrap;
+    let mut fs_root = None;
+    let mut seed = None;
+    // natives can't do anything that isn't granted
+    let mut policy = Policy::deny_all();
+    let mut audit = false;
+    let mut plugins = vec![];
+    let mut libs = vec![];
+    let mut prog_args = None;
+    let mut input: Box<dyn BufRead> = Box::new(BufReader::new(stdin()));
 
     {// arg parsing - no need to hold the copied string in mem
         let args: Vec<_> = std::env::args().collect();
@@ -59,23 +176,142 @@ fn main() -> ExitCode {
             else if args[i] == "-m" {debug_mem=true}
             else if args[i] == "-f" {print_type = PrintType::F64}
             else if args[i] == "-b" {print_type = PrintType::HEX}
-            else if args[i] == "-s" { i += 1;
-                stack_cap = match args[i].parse::<usize>() {
-                    Ok(v) => v,
+            else if args[i] == "--linear" {mem_model = MemModel::Linear}
+            else if args[i] == "--linear-stack" {mem_model = MemModel::LinearStack}
+            else if args[i] == "--sanitize=memory" {sanitize = Some(SanitizeMode::Error)}
+            else if args[i] == "--sanitize=memory-warn" {sanitize = Some(SanitizeMode::Warn)}
+            else if args[i] == "--" {
+                prog_args = Some([&args[1..2], &args[i+1..]].concat());
+                break;
+            }
+            else if args[i] == "--allow-all" {policy = Policy::allow_all()}
+            else if args[i] == "--audit" {audit = true}
+            else if args[i] == "--allow" { i += 1;
+                let Some(caps) = args.get(i) else {
+                    eprintln!("Missing value for capabilities");
+                    return 1.into();
+                };
+                for cap in caps.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+                    policy.allow(cap);
+                }
+            }
+            else if args[i] == "--plugin" { i += 1;
+                let Some(path) = args.get(i) else {
+                    eprintln!("Missing value for plugin");
+                    return 1.into();
+                };
+                plugins.push(path.clone());
+            }
+            else if args[i] == "--lib" { i += 1;
+                let Some(path) = args.get(i) else {
+                    eprintln!("Missing value for library");
+                    return 1.into();
+                };
+                libs.push(path.clone());
+            }
+            else if args[i] == "--policy" { i += 1;
+                let Some(path) = args.get(i) else {
+                    eprintln!("Missing value for policy file");
+                    return 1.into();
+                };
+                match std::fs::read_to_string(path) {
+                    Ok(text) => for cap in Policy::parse(&text).grants() {policy.allow(cap)},
                     Err(e) => {
-                        eprintln!("Error while parsing stack size: {e}");
+                        eprintln!("Error while reading policy file {path}: {e}");
                         return 1.into();
                     }
-                };
+                }
+            }
+            else if args[i] == "-s" { i += 1;
+                let Some(v) = parse_size(&args, i, "stack size") else {return 1.into()};
+                stack_cap = v;
             }
             else if args[i] == "-a" { i += 1;
-                arena_size = match args[i].parse::<usize>() {
-                    Ok(v) => v,
+                let Some(v) = parse_size(&args, i, "arena size") else {return 1.into()};
+                arena_size = v;
+            }
+            else if args[i] == "--overflow" { i += 1;
+                overflow = match args.get(i).map(|s| s.as_str()) {
+                    Some("wrap") => OverflowMode::Wrap,
+                    Some("check") => OverflowMode::Check,
+                    Some("saturate") => OverflowMode::Saturate,
+                    _ => {
+                        eprintln!("Error while parsing overflow mode, expected wrap, check or saturate");
+                        return 1.into();
+                    }
+                };
+            }
+            else if args[i] == "--watch" || args[i] == "--watch-read" || args[i] == "--watch-rw" {
+                let kind = match args[i].as_str() {
+                    "--watch" => WatchKind::Write,
+                    "--watch-read" => WatchKind::Read,
+                    _ => WatchKind::ReadWrite,
+                };
+                i += 1;
+                match parse_watch(args.get(i), kind) {
+                    Some(wp) => watchpoints.push(wp),
+                    None => {
+                        eprintln!("Error while parsing watchpoint, expected [cN:]ADR[+LEN]");
+                        return 1.into();
+                    }
+                }
+            }
+            else if args[i] == "--fs-root" || args[i] == "--fs-root-rw" {
+                let writable = args[i] == "--fs-root-rw";
+                i += 1;
+                let Some(dir) = args.get(i) else {
+                    eprintln!("Missing value for file system root");
+                    return 1.into();
+                };
+                match files::Sandbox::new(std::path::Path::new(dir), writable) {
+                    Ok(sandbox) => fs_root = Some(sandbox),
                     Err(e) => {
-                        eprintln!("Error while parsing arena size: {e}");
+                        eprintln!("Error while opening file system root {dir}: {e}");
                         return 1.into();
                     }
+                }
+            }
+            else if args[i] == "--stdin" { i += 1;
+                let Some(path) = args.get(i) else {
+                    eprintln!("Missing value for input file");
+                    return 1.into();
                 };
+                match File::open(path) {
+                    Ok(f) => input = Box::new(BufReader::new(f)),
+                    Err(e) => {
+                        eprintln!("Error while opening input file {path}: {e}");
+                        return 1.into();
+                    }
+                }
+            }
+            else if args[i] == "--seed" { i += 1;
+                match args.get(i).map(|a| a.parse::<u64>()) {
+                    Some(Ok(v)) => seed = Some(v),
+                    _ => {
+                        eprintln!("Error while parsing seed, expected an unsigned number");
+                        return 1.into();
+                    }
+                }
+            }
+            else if args[i] == "--max-stack" { i += 1;
+                let Some(v) = parse_size(&args, i, "stack limit") else {return 1.into()};
+                limits.max_stack = v;
+            }
+            else if args[i] == "--max-arena" { i += 1;
+                let Some(v) = parse_size(&args, i, "arena limit") else {return 1.into()};
+                limits.max_arena = v;
+            }
+            else if args[i] == "--max-dyn" { i += 1;
+                let Some(v) = parse_size(&args, i, "dynamic memory limit") else {return 1.into()};
+                limits.max_dyn_bytes = v;
+            }
+            else if args[i] == "--max-chunks" { i += 1;
+                let Some(v) = parse_size(&args, i, "chunk limit") else {return 1.into()};
+                limits.max_chunks = v;
+            }
+            else if args[i] == "--max-output" { i += 1;
+                let Some(v) = parse_size(&args, i, "output limit") else {return 1.into()};
+                limits.max_output = v;
             }
             else {
                 eprintln!("Error while parsing arguments\nhelp page:");
@@ -86,38 +322,108 @@ fn main() -> ExitCode {
         }
     }
 
-    let mut vm = Lada::init(prog, stack_cap, arena_size);
+    let mut vm = match Lada::init_with_limits(prog, stack_cap, arena_size, mem_model, limits) {
+        Ok(vm) => vm,
+        Err(e) => {
+            eprintln!("ERROR: {:?}, the program doesn't fit in the given limits", e);
+            return 1.into();
+        }
+    };
+    if let Some(sandbox) = fs_root {
+        files::register(vm.natives_mut(), sandbox);
+    }
+    vm.set_host(Box::new(StdHost::with_input(input)));
+    input::register(vm.natives_mut());
+    let seed = seed.unwrap_or_else(|| std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64));
+    random::register(vm.natives_mut(), seed);
+    for path in plugins {
+        if let Err(e) = plugin::load(vm.natives_mut(), std::path::Path::new(&path)) {
+            eprintln!("Error while loading plugin {path}: {e}");
+            return 1.into();
+        }
+    }
+    if !vm.externs().is_empty() {
+        if let Err(e) = register_externs(&mut vm, &libs) {
+            eprintln!("Error while resolving extern functions: {e}");
+            return 1.into();
+        }
+    }
+    vm.set_policy(policy);
+    if audit {
+        return audit_natives(&vm).into();
+    }
+    if let Err(e) = vm.link() {
+        eprintln!("ERROR: {:?}, the program imports a native that isn't available", e);
+        return 1.into();
+    }
+    vm.set_sanitizer(sanitize);
+    vm.set_overflow_mode(overflow);
+    for wp in watchpoints {
+        vm.add_watchpoint(wp, None);
+    }
+    if let Some(prog_args) = prog_args {
+        if let Err(e) = vm.set_args(&prog_args) {
+            eprintln!("ERROR: {:?}, the program arguments don't fit", e);
+            return 1.into();
+        }
+    }
     let mut ip = 0;
     while !vm.halted() {
-        match vm.exec_inst(&print_type) {
-            Ok(_) => {
+        let res = vm.exec_inst(&print_type);
+        let reports = vm.take_sanitizer_reports();
+        // debug output and errors are printed directly, the buffered output of the program has to come first
+        if debug || debug_arena || debug_mem || res != Ok(Step::Done) || !reports.is_empty() {
+            let _ = vm.flush();
+        }
+        for r in reports {
+            eprintln!("{}: read of {} uninitialized byte(s) at address {}, Instruction: {}: {}",
+                      if sanitize == Some(SanitizeMode::Error) {"ERROR"} else {"WARNING"}, r.len, r.addr, r.ip, vm.inst(r.ip));
+        }
+        match res {
+            Ok(Step::Watch) => {
+                if let Some(hit) = vm.last_watch_hit() {
+                    println!("Watchpoint {} {} at address {}: {} -> {}, Instruction: {}: {}",
+                             hit.id, if hit.write {"write"} else {"read"}, hit.addr, hit.old, hit.new, hit.ip, vm.inst(hit.ip));
+                }
+                if !debug_step { return 1.into(); }
+                wait_step();
+                ip = vm.ip();
+            }
+            Ok(Step::Done) => {
                 if debug || debug_arena || debug_mem {print!("Inst: {}: {}    \t", ip, vm.inst(ip));}
-                if debug {vm.print_stack(&print_type);}
+                if debug {println!("{}", vm.stack_str(&print_type));}
+                if debug && vm.fp() > 0 {println!("Frames: {}", vm.frames_str(&print_type));}
                 if debug_arena {print!("Arena memory: ");
                     match print_type {
                         PrintType::I64 => {println!("{:?}",  vm.get_arena());}
                         _ => {println!("{:x?}", vm.get_arena());}
                     }
                 }
-                if debug_mem {print!("Dynamic memory: ");
+                if debug_mem && mem_model != MemModel::Split {
+                    println!("Heap blocks: {:?}", vm.get_heap().blocks());
+                } else if debug_mem {print!("Dynamic memory: ");
                     match print_type {
                         PrintType::I64 => {println!("{:?}",  vm.get_dyn_mem());}
                         _ => {println!("{:x?}", vm.get_dyn_mem());}
                     }
                 }
-                if debug_step {
-                    let mut s= String::new();
-                    stdin().read_line(&mut s).unwrap();
-                }ip = vm.ip()
+                if debug_step {wait_step()}
+                ip = vm.ip()
             }
             Err(e) => {
-                if stack_resize && e == ExecErr::StackOverflow { vm.stack_extend(8); continue; }
-                if arena_resize && e == ExecErr::IllegalMemAccess {
+                let e = if stack_resize && e == ExecErr::StackOverflow {
+                    match vm.stack_extend(8) { Ok(_) => continue, Err(e) => e }
+                } else {e};
+                let e = if arena_resize && e == ExecErr::IllegalMemAccess {
                     match vm.last_err_inst() {
-                        InstType::READ_8  | InstType::READ_16  | InstType::READ_32  | InstType::READ_64 |
-                        InstType::WRITE_8 | InstType::WRITE_16 | InstType::WRITE_32 | InstType::WRITE_64 => {
-                            vm.resize_arena(vm.get_stack_top(1)[0] as usize +8);
-                            continue;
+                        i if i.accesses_memory() => {
+                            // immediate forms carry the address in the instruction
+                            let adr = if vm.inst(vm.ip()).has_op {vm.inst(vm.ip()).operand} else {vm.get_stack_top(1)[0]};
+                            match vm.resize_arena((adr as usize).saturating_add(8)) {
+                                Ok(_) => continue,
+                                Err(e) => e
+                            }
                         }
                         _ => {
                             eprintln!("\nERROR: {:?}, Instruciton: {:?}", e, vm.inst(vm.ip()));
@@ -125,7 +431,7 @@ fn main() -> ExitCode {
                             return 1.into();
                         }
                     }
-                }
+                } else {e};
                 if debug {eprintln!("{:#?}", vm)}
                 eprintln!("\nERROR: {:?}, Instruciton: {}", e,
                           if vm.prog_len() > vm.ip() {
@@ -1,5 +1,5 @@
 #!/usr/bin/env sh
 ./lc src/examples/arena.lv src/examples/arena.lb &&
-./lv src/examples/arena.lb -d -b -a 16
+./lv src/examples/arena.lb -d -b -a 24
 echo
-./lv src/examples/arena.lb -b -a 16
+./lv src/examples/arena.lb -b -a 24
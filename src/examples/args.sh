@@ -0,0 +1,3 @@
+#!/usr/bin/env sh
+./lc src/examples/args.lv src/examples/args.lb &&
+./lv src/examples/args.lb --allow io.stdout -- hello "two words"
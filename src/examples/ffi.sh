@@ -0,0 +1,3 @@
+#!/usr/bin/env sh
+./lc src/examples/ffi.lv src/examples/ffi.lb &&
+./lv src/examples/ffi.lb -f --lib libm.so.6 --allow ffi
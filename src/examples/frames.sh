@@ -0,0 +1,3 @@
+#!/usr/bin/env sh
+./lc src/examples/frames.lv src/examples/frames.lb &&
+./lv src/examples/frames.lb -f -d
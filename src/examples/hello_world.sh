@@ -1,3 +1,3 @@
 #!/usr/bin/env sh
 ./lc src/examples/hello_world.lv src/examples/hello_world.lb &&
-./lv src/examples/hello_world.lb
+./lv src/examples/hello_world.lb --allow io.stdout
@@ -1,3 +1,3 @@
 #!/usr/bin/env sh
 ./lc src/examples/native.lv src/examples/native.lb &&
-./lv src/examples/native.lb -R
+./lv src/examples/native.lb -R --allow io.stdout
@@ -0,0 +1,78 @@
+// a native plugin, build it with
+// rustc --edition 2021 --crate-type cdylib -O src/examples/plugin.rs -o src/examples/libstrings.so
+// the types mirror include/lv_plugin.h
+use std::ffi::{c_char, c_void};
+
+#[repr(C)]
+pub struct Ctx {
+    api: *const Api,
+}
+
+#[repr(C)]
+pub struct Registrar {
+    api: *const Api,
+}
+
+type Native = extern "C" fn(ctx: *mut Ctx, data: *mut c_void) -> i32;
+
+#[repr(C)]
+pub struct Api {
+    version: u32,
+    register_native: extern "C" fn(reg: *mut Registrar, name: *const c_char, capability: *const c_char,
+                                   arity: usize, func: Native, data: *mut c_void) -> i32,
+    pop: extern "C" fn(ctx: *mut Ctx, out: *mut isize) -> i32,
+    push: extern "C" fn(ctx: *mut Ctx, v: isize) -> i32,
+    read_bytes: extern "C" fn(ctx: *mut Ctx, ptr: isize, buf: *mut u8, len: usize) -> i32,
+    write_bytes: extern "C" fn(ctx: *mut Ctx, ptr: isize, buf: *const u8, len: usize) -> i32,
+    output: extern "C" fn(ctx: *mut Ctx, s: *const u8, len: usize) -> i32,
+}
+
+// returns early with the error of a failed api call
+macro_rules! check {
+    ($e:expr) => {
+        match $e { 0 => {} e => return e }
+    };
+}
+
+fn pop(ctx: *mut Ctx) -> Result<isize, i32> {
+    let mut v = 0;
+    let api = unsafe { &*(*ctx).api };
+    match (api.pop)(ctx, &mut v) { 0 => Ok(v), e => Err(e) }
+}
+
+// address, length -> the bytes are reversed in place
+extern "C" fn str_reverse(ctx: *mut Ctx, _: *mut c_void) -> i32 {
+    let api = unsafe { &*(*ctx).api };
+    let (len, adr) = match (pop(ctx), pop(ctx)) {
+        (Ok(len), Ok(adr)) => (len, adr),
+        (Err(e), _) | (_, Err(e)) => return e,
+    };
+    let mut buf = vec![0u8; len.max(0) as usize];
+    check!((api.read_bytes)(ctx, adr, buf.as_mut_ptr(), buf.len()));
+    buf.reverse();
+    check!((api.write_bytes)(ctx, adr, buf.as_ptr(), buf.len()));
+    0
+}
+
+// address, length, byte -> how often byte is in the string
+extern "C" fn str_count(ctx: *mut Ctx, _: *mut c_void) -> i32 {
+    let api = unsafe { &*(*ctx).api };
+    let (byte, len, adr) = match (pop(ctx), pop(ctx), pop(ctx)) {
+        (Ok(b), Ok(len), Ok(adr)) => (b, len, adr),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => return e,
+    };
+    let mut buf = vec![0u8; len.max(0) as usize];
+    check!((api.read_bytes)(ctx, adr, buf.as_mut_ptr(), buf.len()));
+    (api.push)(ctx, buf.iter().filter(|b| **b as isize == byte).count() as isize)
+}
+
+#[no_mangle]
+pub extern "C" fn lv_plugin_init(reg: *mut Registrar) -> i32 {
+    let api = unsafe { &*(*reg).api };
+    if api.version < 1 {
+        return -1;
+    }
+    check!((api.register_native)(reg, c"str_reverse".as_ptr(), c"strings".as_ptr(), 2, str_reverse, std::ptr::null_mut()));
+    check!((api.register_native)(reg, c"str_count".as_ptr(), c"strings".as_ptr(), 3, str_count, std::ptr::null_mut()));
+    0
+}
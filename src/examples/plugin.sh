@@ -0,0 +1,4 @@
+#!/usr/bin/env sh
+rustc --edition 2021 --crate-type cdylib -O src/examples/plugin.rs -o src/examples/libstrings.so &&
+./lc src/examples/plugin.lv src/examples/plugin.lb &&
+./lv src/examples/plugin.lb --plugin src/examples/libstrings.so --allow strings,io.stdout
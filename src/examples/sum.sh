@@ -0,0 +1,3 @@
+#!/usr/bin/env sh
+./lc src/examples/sum.lv src/examples/sum.lb &&
+echo 1 2 3 4 | ./lv src/examples/sum.lb --allow io.stdin
@@ -0,0 +1,179 @@
+// C functions declared with `%extern name (params) -> ret` and called like natives with `native name`
+use std::fmt;
+
+// how a value is passed, ptr translates vm addresses to host pointers, 0 stays null;
+// cptr is a pointer the function only reads through, it can also point into read-only data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CType {
+    Void,
+    I32,
+    I64,
+    F32,
+    F64,
+    Ptr,
+    CPtr,
+}
+
+impl CType {
+    fn parse(s: &str) -> Option<CType> {
+        match s {
+            "void" => Some(CType::Void),
+            "i32" => Some(CType::I32),
+            "i64" => Some(CType::I64),
+            "f32" => Some(CType::F32),
+            "f64" => Some(CType::F64),
+            "ptr" => Some(CType::Ptr),
+            "cptr" => Some(CType::CPtr),
+            _ => None,
+        }
+    }
+
+    pub fn from_byte(b: u8) -> Option<CType> {
+        [CType::Void, CType::I32, CType::I64, CType::F32, CType::F64, CType::Ptr, CType::CPtr].get(b as usize).copied()
+    }
+
+    fn is_float(self) -> bool {matches!(self, CType::F32 | CType::F64)}
+}
+
+impl fmt::Display for CType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CType::Void => "void",
+            CType::I32 => "i32",
+            CType::I64 => "i64",
+            CType::F32 => "f32",
+            CType::F64 => "f64",
+            CType::Ptr => "ptr",
+            CType::CPtr => "cptr",
+        })
+    }
+}
+
+// integer and float arguments go in their own registers, stack arguments aren't supported
+pub const MAX_INT_PARAMS: usize = 6;
+pub const MAX_FLOAT_PARAMS: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extern {
+    pub name: String,
+    pub params: Vec<CType>,
+    pub ret: CType,
+}
+
+impl Extern {
+    // `name (t, t) -> t`, without `->` the function returns void
+    pub fn parse(decl: &str) -> Option<Extern> {
+        let (name, rest) = decl.trim().split_once('(')?;
+        let (params, ret) = rest.split_once(')')?;
+        let params = params.split([',', ' ']).filter(|p| !p.is_empty())
+            .map(|p| CType::parse(p).filter(|t| *t != CType::Void))
+            .collect::<Option<Vec<_>>>()?;
+        let ret = match ret.trim() {
+            "" => CType::Void,
+            r => CType::parse(r.strip_prefix("->")?.trim())?,
+        };
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) ||
+            params.iter().filter(|t| !t.is_float()).count() > MAX_INT_PARAMS ||
+            params.iter().filter(|t| t.is_float()).count() > MAX_FLOAT_PARAMS {
+            return None;
+        }
+        Some(Extern { name: name.to_string(), params, ret })
+    }
+}
+
+impl fmt::Display for Extern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%extern {} (", self.name)?;
+        for (i, p) in self.params.iter().enumerate() {
+            write!(f, "{}{p}", if i > 0 {", "} else {""})?;
+        }
+        write!(f, ") -> {}", self.ret)
+    }
+}
+
+#[cfg(all(unix, target_arch = "x86_64"))]
+pub use sysv::register;
+
+// every integer argument goes in rdi..r9 and every float one in xmm0..xmm7 independently of their order,
+// so one signature with all of them can call any function that fits in the registers
+#[cfg(all(unix, target_arch = "x86_64"))]
+mod sysv {
+    use std::{ffi::CString, io, path::Path, rc::Rc};
+
+    use super::{CType, Extern, MAX_FLOAT_PARAMS, MAX_INT_PARAMS};
+    use crate::{ExecErr, to_f64, to_isize};
+    use crate::native::{NativeCtx, NativeRegistry, FFI};
+    use crate::plugin::Library;
+
+    type IntFn = extern "C" fn(i64, i64, i64, i64, i64, i64, f64, f64, f64, f64, f64, f64, f64, f64) -> i64;
+    type FloatFn = extern "C" fn(i64, i64, i64, i64, i64, i64, f64, f64, f64, f64, f64, f64, f64, f64) -> f64;
+
+    // f32 is read from the low half of the register
+    fn f32_reg(v: f32) -> f64 {f64::from_bits(v.to_bits() as u64)}
+
+    fn call(ctx: &mut NativeCtx, ext: &Extern, sym: *mut std::ffi::c_void) -> Result<(), ExecErr> {
+        let mut values = vec![0; ext.params.len()];
+        for v in values.iter_mut().rev() {
+            *v = ctx.pop()?;
+        }
+        let mut ints = [0i64; MAX_INT_PARAMS];
+        let mut floats = [0f64; MAX_FLOAT_PARAMS];
+        let (mut n_int, mut n_float) = (0, 0);
+        for (t, v) in ext.params.iter().zip(values) {
+            match t {
+                CType::F32 => { floats[n_float] = f32_reg(to_f64(v) as f32); n_float += 1; }
+                CType::F64 => { floats[n_float] = to_f64(v); n_float += 1; }
+                CType::I32 => { ints[n_int] = v as i32 as i64; n_int += 1; }
+                CType::I64 => { ints[n_int] = v as i64; n_int += 1; }
+                CType::Ptr => { ints[n_int] = if v == 0 {0} else {ctx.host_ptr(v)? as i64}; n_int += 1; }
+                CType::CPtr => { ints[n_int] = if v == 0 {0} else {ctx.host_const_ptr(v)? as i64}; n_int += 1; }
+                CType::Void => unreachable!(),
+            }
+        }
+        let [i0, i1, i2, i3, i4, i5] = ints;
+        let [f0, f1, f2, f3, f4, f5, f6, f7] = floats;
+        let ret = if ext.ret.is_float() {
+            let func: FloatFn = unsafe { std::mem::transmute(sym) };
+            let r = func(i0, i1, i2, i3, i4, i5, f0, f1, f2, f3, f4, f5, f6, f7);
+            match ext.ret {
+                CType::F32 => to_isize(f32::from_bits(r.to_bits() as u32) as f64),
+                _ => to_isize(r),
+            }
+        } else {
+            let func: IntFn = unsafe { std::mem::transmute(sym) };
+            let r = func(i0, i1, i2, i3, i4, i5, f0, f1, f2, f3, f4, f5, f6, f7);
+            match ext.ret {
+                CType::I32 => r as i32 as isize,
+                _ => r as isize,
+            }
+        };
+        if ext.ret == CType::Void {
+            return Ok(());
+        }
+        ctx.push(ret)
+    }
+
+    // resolves every extern in the libraries, in their order, then in the program itself (libc),
+    // and registers it as a native needing the ffi capability
+    pub fn register(natives: &mut NativeRegistry, externs: &[Extern], libs: &[&Path]) -> io::Result<()> {
+        let mut opened = vec![];
+        for lib in libs {
+            opened.push(Rc::new(Library::open(Some(lib))?));
+        }
+        opened.push(Rc::new(Library::open(None)?));
+        for ext in externs {
+            let c_name = CString::new(ext.name.as_str()).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+            let Some((lib, sym)) = opened.iter().find_map(|l| l.symbol(&c_name).map(|s| (l.clone(), s))) else {
+                return Err(io::Error::new(io::ErrorKind::NotFound, format!("symbol {} not found", ext.name)));
+            };
+            let ext_c = ext.clone();
+            natives.register(&ext.name, FFI, ext.params.len(), move |ctx| {
+                let _lib = &lib;
+                call(ctx, &ext_c, sym)
+            });
+        }
+        Ok(())
+    }
+}
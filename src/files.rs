@@ -0,0 +1,205 @@
+// file natives restricted to one directory, failures are returned to the program as negative codes
+use std::{cell::RefCell, fs::{File, OpenOptions}, io::{self, Read, Seek, SeekFrom, Write}, path::{Component, Path, PathBuf}, rc::Rc};
+
+use super::ExecErr;
+use super::native::{NativeCtx, NativeRegistry, FS_READ, FS_WRITE};
+
+pub const NOT_FOUND: isize = -1;
+// outside of the root, or writing without write access
+pub const PERMISSION: isize = -2;
+pub const BAD_HANDLE: isize = -3;
+// unknown mode or whence, path that isn't utf-8
+pub const INVALID: isize = -4;
+pub const IO_ERROR: isize = -5;
+
+// open modes
+pub const READ: isize = 0;
+// creates or truncates
+pub const WRITE: isize = 1;
+// creates, writes go to the end
+pub const APPEND: isize = 2;
+
+pub struct Sandbox {
+    root: PathBuf,
+    writable: bool,
+    handles: Vec<Option<File>>,
+}
+
+impl Sandbox {
+    pub fn new(root: &Path, writable: bool) -> io::Result<Sandbox> {
+        Ok(Sandbox { root: root.canonicalize()?, writable, handles: vec![] })
+    }
+
+    // paths are relative to the root, a leading / is ignored
+    fn resolve(&self, path: &str) -> Result<PathBuf, isize> {
+        let rel = Path::new(path.trim_start_matches('/'));
+        if rel.components().any(|c| !matches!(c, Component::Normal(_) | Component::CurDir)) {
+            return Err(PERMISSION);
+        }
+        let full = self.root.join(rel);
+        // symlinks inside of the root could still point out of it
+        let real = match full.canonicalize() {
+            Ok(p) => p,
+            // a dangling symlink would be followed when the file is created
+            Err(_) if full.symlink_metadata().is_ok_and(|m| m.file_type().is_symlink()) => return Err(PERMISSION),
+            Err(_) => match (full.parent().map(|p| p.canonicalize()), full.file_name()) {
+                (Some(Ok(dir)), Some(name)) => dir.join(name),
+                _ => return Err(NOT_FOUND),
+            }
+        };
+        if !real.starts_with(&self.root) {
+            return Err(PERMISSION);
+        }
+        Ok(real)
+    }
+
+    fn open(&mut self, path: &str, mode: isize) -> Result<isize, isize> {
+        let path = self.resolve(path)?;
+        let mut options = OpenOptions::new();
+        match mode {
+            READ => options.read(true),
+            WRITE | APPEND if !self.writable => return Err(PERMISSION),
+            WRITE => options.write(true).create(true).truncate(true),
+            APPEND => options.append(true).create(true),
+            _ => return Err(INVALID),
+        };
+        let file = options.open(path).map_err(io_code)?;
+        let handle = match self.handles.iter().position(|h| h.is_none()) {
+            Some(i) => i,
+            None => {
+                self.handles.push(None);
+                self.handles.len()-1
+            }
+        };
+        self.handles[handle] = Some(file);
+        Ok(handle as isize)
+    }
+
+    fn file(&mut self, handle: isize) -> Result<&mut File, isize> {
+        let handle = usize::try_from(handle).map_err(|_| BAD_HANDLE)?;
+        self.handles.get_mut(handle).and_then(|h| h.as_mut()).ok_or(BAD_HANDLE)
+    }
+}
+
+fn io_code(e: io::Error) -> isize {
+    match e.kind() {
+        io::ErrorKind::NotFound => NOT_FOUND,
+        io::ErrorKind::PermissionDenied => PERMISSION,
+        _ => IO_ERROR,
+    }
+}
+
+// reads until buf is full or the file ends
+fn read_full(f: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match f.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(k) => n += k,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+// the native's result is pushed as is, errors as their code
+fn ret(ctx: &mut NativeCtx, res: Result<isize, isize>) -> Result<(), ExecErr> {
+    ctx.push(res.unwrap_or_else(|e| e))
+}
+
+fn path(ctx: &mut NativeCtx) -> Result<Result<String, isize>, ExecErr> {
+    let len = ctx.pop()?;
+    let adr = ctx.pop()?;
+    let len = usize::try_from(len).map_err(|_| ExecErr::IllegalMemAccess)?;
+    let bytes = ctx.read_bytes(adr, len)?;
+    Ok(String::from_utf8(bytes).map_err(|_| INVALID))
+}
+
+// open and write in WRITE or APPEND mode need io.fs.write, everything else io.fs.read
+// open (path address, path length, mode) -> handle
+// read (handle, address, length) -> bytes read, 0 at the end of the file
+// write (handle, address, length) -> bytes written
+// seek (handle, offset, whence: 0 start, 1 current, 2 end) -> new position
+// close (handle) -> 0
+// stat (path address, path length) -> size of the file
+pub fn register(natives: &mut NativeRegistry, sandbox: Sandbox) {
+    let sandbox = Rc::new(RefCell::new(sandbox));
+
+    let sb = sandbox.clone();
+    natives.register("open", FS_READ, 3, move |ctx| {
+        let mode = ctx.pop()?;
+        if mode == WRITE || mode == APPEND {
+            ctx.require(FS_WRITE)?;
+        }
+        let res = path(ctx)?.and_then(|p| sb.borrow_mut().open(&p, mode));
+        ret(ctx, res)
+    });
+
+    let sb = sandbox.clone();
+    natives.register("read", FS_READ, 3, move |ctx| {
+        let len = ctx.pop()?;
+        let adr = ctx.pop()?;
+        let handle = ctx.pop()?;
+        let Ok(len) = u64::try_from(len) else { return ret(ctx, Err(INVALID)) };
+        let mut sb = sb.borrow_mut();
+        let f = match sb.file(handle) {
+            Ok(f) => f,
+            Err(e) => return ret(ctx, Err(e)),
+        };
+        // only the part of the buffer the rest of the file fills has to be valid
+        let len = match (f.metadata(), f.stream_position()) {
+            (Ok(m), Ok(pos)) if m.is_file() => len.min(m.len().saturating_sub(pos)),
+            _ => len,
+        };
+        let len = usize::try_from(len).map_err(|_| ExecErr::IllegalMemAccess)?;
+        // the file is only read once the destination is known to be good, so a bad one doesn't lose data
+        let res = ctx.write_with(adr, len, |buf| read_full(f, buf).map_err(io_code))?;
+        ret(ctx, res.map(|n| n as isize))
+    });
+
+    let sb = sandbox.clone();
+    natives.register("write", FS_WRITE, 3, move |ctx| {
+        let len = ctx.pop()?;
+        let adr = ctx.pop()?;
+        let handle = ctx.pop()?;
+        let len = usize::try_from(len).map_err(|_| ExecErr::IllegalMemAccess)?;
+        let data = ctx.read_bytes(adr, len)?;
+        let res = sb.borrow_mut().file(handle).and_then(|f| f.write_all(&data).map_err(io_code));
+        ret(ctx, res.map(|_| len as isize))
+    });
+
+    let sb = sandbox.clone();
+    natives.register("seek", FS_READ, 3, move |ctx| {
+        let whence = ctx.pop()?;
+        let offset = ctx.pop()?;
+        let handle = ctx.pop()?;
+        let pos = match whence {
+            0 if offset >= 0 => SeekFrom::Start(offset as u64),
+            1 => SeekFrom::Current(offset as i64),
+            2 => SeekFrom::End(offset as i64),
+            _ => return ret(ctx, Err(INVALID)),
+        };
+        let res = sb.borrow_mut().file(handle).and_then(|f| f.seek(pos).map_err(io_code));
+        ret(ctx, res.map(|p| p as isize))
+    });
+
+    let sb = sandbox.clone();
+    natives.register("close", FS_READ, 1, move |ctx| {
+        let handle = ctx.pop()?;
+        let mut sb = sb.borrow_mut();
+        let res = sb.file(handle).map(|_| 0);
+        if res.is_ok() {
+            sb.handles[handle as usize] = None;
+        }
+        ret(ctx, res)
+    });
+
+    natives.register("stat", FS_READ, 2, move |ctx| {
+        let res = path(ctx)?
+            .and_then(|p| sandbox.borrow().resolve(&p))
+            .and_then(|p| p.metadata().map_err(io_code))
+            .map(|m| m.len() as isize);
+        ret(ctx, res)
+    });
+}
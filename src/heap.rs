@@ -0,0 +1,68 @@
+// first fit allocator for the linear memory model
+// blocks live in the vm memory, the bookkeeping is kept outside of it so programs can't corrupt it
+use std::collections::BTreeMap;
+
+use super::{ExecErr, LimitKind};
+
+const ALIGN: usize = 8;
+
+#[derive(Debug, Default)]
+pub struct Heap {
+    // end of the last block handed out, everything in memory after it is free
+    top: usize,
+    // start -> size
+    free: BTreeMap<usize, usize>,
+    used: BTreeMap<usize, usize>,
+}
+
+// size of the block handed out for a request of `size` bytes, None if it doesn't fit in usize
+pub fn block_size(size: usize) -> Option<usize> {
+    size.max(1).checked_next_multiple_of(ALIGN)
+}
+
+impl Heap {
+    pub fn blocks(&self) -> &BTreeMap<usize, usize> {&self.used}
+    pub fn end(&self) -> usize {self.top}
+
+    // returns the address of a zeroed block of at least `size` bytes, grows the memory if needed
+    pub fn alloc(&mut self, mem: &mut Vec<u8>, size: usize, max_mem: usize) -> Result<usize, ExecErr> {
+        let size = block_size(size).ok_or(ExecErr::ResourceLimit(LimitKind::Arena))?;
+        // memory could have been resized past the heap, don't hand that out
+        self.top = self.top.max(mem.len()).div_ceil(ALIGN) * ALIGN;
+
+        let adr = if let Some((&adr, &len)) = self.free.iter().find(|(_, &len)| len >= size) {
+            self.free.remove(&adr);
+            if len > size { self.free.insert(adr+size, len-size); }
+            mem[adr..adr+size].fill(0);
+            adr
+        } else {
+            let adr = self.top;
+            let top = adr.checked_add(size).ok_or(ExecErr::ResourceLimit(LimitKind::Arena))?;
+            if top > max_mem { return Err(ExecErr::ResourceLimit(LimitKind::Arena)); }
+            crate::try_grow(mem, top, LimitKind::Arena)?;
+            self.top = top;
+            adr
+        };
+        self.used.insert(adr, size);
+        Ok(adr)
+    }
+
+    // returns the size of the freed block or None if `adr` isn't the start of one
+    pub fn free(&mut self, adr: usize) -> Option<usize> {
+        let size = self.used.remove(&adr)?;
+        let mut start = adr;
+        let mut len = size;
+        if let Some((&prev, &prev_len)) = self.free.range(..adr).next_back() {
+            if prev + prev_len == adr {
+                self.free.remove(&prev);
+                start = prev;
+                len += prev_len;
+            }
+        }
+        if let Some(next_len) = self.free.remove(&(adr+size)) {
+            len += next_len;
+        }
+        self.free.insert(start, len);
+        Some(size)
+    }
+}
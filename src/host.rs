@@ -0,0 +1,104 @@
+// what the vm and its natives use of the outside world, swap it to embed the vm or to capture its output
+use std::{cell::RefCell, collections::HashMap, io::{self, BufRead, BufReader, BufWriter, Cursor, Stdout, Write}, rc::Rc, thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+pub trait Host {
+    fn stdout(&mut self) -> &mut dyn Write;
+    fn stderr(&mut self) -> &mut dyn Write;
+    fn stdin(&mut self) -> &mut dyn BufRead;
+    // nanoseconds, never decreasing
+    fn monotonic_ns(&mut self) -> isize;
+    // nanoseconds since the unix epoch, negative before it
+    fn wall_ns(&mut self) -> isize;
+    fn sleep(&mut self, d: Duration) {
+        thread::sleep(d);
+    }
+    // None when the variable isn't set
+    fn env(&mut self, name: &str) -> Option<String> {
+        std::env::var(name).ok()
+    }
+}
+
+// the process' stdio, stdout is written in blocks instead of once per PRINT
+pub struct StdHost {
+    out: BufWriter<Stdout>,
+    err: io::Stderr,
+    input: Box<dyn BufRead>,
+    start: Instant,
+}
+
+impl StdHost {
+    pub fn new() -> StdHost {
+        StdHost::with_input(Box::new(BufReader::new(io::stdin())))
+    }
+
+    // input read from something else than stdin, like a file
+    pub fn with_input(input: Box<dyn BufRead>) -> StdHost {
+        StdHost { out: BufWriter::new(io::stdout()), err: io::stderr(), input, start: Instant::now() }
+    }
+}
+
+impl Default for StdHost {
+    fn default() -> StdHost {StdHost::new()}
+}
+
+impl Host for StdHost {
+    fn stdout(&mut self) -> &mut dyn Write {&mut self.out}
+    fn stderr(&mut self) -> &mut dyn Write {&mut self.err}
+    fn stdin(&mut self) -> &mut dyn BufRead {&mut self.input}
+    fn monotonic_ns(&mut self) -> isize {self.start.elapsed().as_nanos() as isize}
+    fn wall_ns(&mut self) -> isize {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_nanos() as isize,
+            Err(e) => -(e.duration().as_nanos() as isize),
+        }
+    }
+}
+
+// bytes written to a MemHost, clones share the buffer
+#[derive(Debug, Clone, Default)]
+pub struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuf {
+    pub fn bytes(&self) -> Vec<u8> {self.0.borrow().clone()}
+    pub fn text(&self) -> String {String::from_utf8_lossy(&self.0.borrow()).into_owned()}
+    pub fn clear(&self) {self.0.borrow_mut().clear()}
+}
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {Ok(())}
+}
+
+// everything in memory, time only moves with sleep or by setting `now_ns`, the environment is `env`
+#[derive(Debug, Default)]
+pub struct MemHost {
+    pub out: SharedBuf,
+    pub err: SharedBuf,
+    input: Cursor<Vec<u8>>,
+    pub now_ns: isize,
+    pub env: HashMap<String, String>,
+}
+
+impl MemHost {
+    pub fn new(input: &[u8]) -> MemHost {
+        MemHost { input: Cursor::new(input.to_vec()), ..MemHost::default() }
+    }
+}
+
+impl Host for MemHost {
+    fn stdout(&mut self) -> &mut dyn Write {&mut self.out}
+    fn stderr(&mut self) -> &mut dyn Write {&mut self.err}
+    fn stdin(&mut self) -> &mut dyn BufRead {&mut self.input}
+    fn monotonic_ns(&mut self) -> isize {self.now_ns}
+    fn wall_ns(&mut self) -> isize {self.now_ns}
+    fn sleep(&mut self, d: Duration) {
+        self.now_ns = self.now_ns.saturating_add(d.as_nanos() as isize);
+    }
+    fn env(&mut self, name: &str) -> Option<String> {
+        self.env.get(name).cloned()
+    }
+}
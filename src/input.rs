@@ -0,0 +1,87 @@
+// natives reading the input of the program from the host, lv uses stdin or a file
+use std::io::{BufRead, Read};
+
+use super::{ExecErr, to_isize};
+use super::native::{NativeCtx, NativeRegistry, STDIN};
+use super::files::{INVALID, IO_ERROR};
+
+pub const EOF: isize = -1;
+
+// next whitespace separated word, None at the end of the input
+fn word(input: &mut dyn BufRead) -> Result<Option<String>, isize> {
+    let mut word = vec![];
+    loop {
+        let buf = input.fill_buf().map_err(|_| IO_ERROR)?;
+        if buf.is_empty() { break; }
+        let skip = if word.is_empty() {buf.iter().take_while(|b| b.is_ascii_whitespace()).count()} else {0};
+        let len = buf[skip..].iter().take_while(|b| !b.is_ascii_whitespace()).count();
+        word.extend(&buf[skip..skip+len]);
+        let done = skip+len < buf.len() && (len > 0 || !word.is_empty());
+        input.consume(skip+len);
+        if done { break; }
+    }
+    if word.is_empty() { return Ok(None); }
+    String::from_utf8(word).map(Some).map_err(|_| INVALID)
+}
+
+// value and status (0, EOF or an error code)
+fn push_parsed(ctx: &mut NativeCtx, parse: fn(&str) -> Option<isize>) -> Result<(), ExecErr> {
+    let (value, status) = match word(ctx.stdin()) {
+        Ok(Some(w)) => match parse(&w) {
+            Some(v) => (v, 0),
+            None => (0, INVALID),
+        },
+        Ok(None) => (0, EOF),
+        Err(e) => (0, e),
+    };
+    ctx.push(value)?;
+    ctx.push(status)
+}
+
+// read_line (address, max length) -> length without the newline, EOF at the end of the input; the whole buffer has to be writable
+// read_byte () -> byte or EOF
+// read_int () -> value, status
+// read_float () -> value, status
+// eof () -> 1 at the end of the input
+pub fn register(natives: &mut NativeRegistry) {
+    natives.register("read_line", STDIN, 2, |ctx| {
+        let max = ctx.pop()?;
+        let adr = ctx.pop()?;
+        let max = usize::try_from(max).map_err(|_| ExecErr::IllegalMemAccess)?;
+        // a bad destination fails before the line is taken from the input
+        ctx.check_writable(adr, max)?;
+        let mut line = vec![];
+        // the rest of a line longer than max is left for the next read
+        if ctx.stdin().take(max as u64).read_until(b'\n', &mut line).is_err() {
+            return ctx.push(IO_ERROR);
+        }
+        if line.is_empty() && max > 0 {
+            return ctx.push(EOF);
+        }
+        if line.ends_with(b"\n") { line.pop(); }
+        if line.ends_with(b"\r") { line.pop(); }
+        ctx.write_bytes(adr, &line)?;
+        ctx.push(line.len() as isize)
+    });
+
+    natives.register("read_byte", STDIN, 0, |ctx| {
+        let mut byte = [0u8];
+        let res = match ctx.stdin().read(&mut byte) {
+            Ok(0) => EOF,
+            Ok(_) => byte[0] as isize,
+            Err(_) => IO_ERROR,
+        };
+        ctx.push(res)
+    });
+
+    natives.register("read_int", STDIN, 0, |ctx| push_parsed(ctx, |w| w.parse().ok()));
+    natives.register("read_float", STDIN, 0, |ctx| push_parsed(ctx, |w| w.parse().ok().map(to_isize)));
+
+    natives.register("eof", STDIN, 0, |ctx| {
+        let eof = match ctx.stdin().fill_buf() {
+            Ok(buf) => buf.is_empty() as isize,
+            Err(_) => IO_ERROR,
+        };
+        ctx.push(eof)
+    });
+}
@@ -1,12 +1,24 @@
 // #[cfg(target_os = "linux")]
 pub mod linux;
+pub mod native;
+pub mod files;
+pub mod input;
+pub mod random;
+#[cfg(unix)]
+pub mod plugin;
+pub mod ffi;
+pub mod host;
+pub mod heap;
+pub mod sanitizer;
+pub mod watch;
 #[cfg(test)]
 mod tests;
 use core::fmt;
-use std::{mem::transmute, isize};
 
 const PTR_OFFSET: usize = 48;
 const PTR_MASK: isize = 0x0000ffffffffffff;
+// where the stack is mapped in linear memory
+const STACK_BASE: isize = 1<<47;
 
 macro_rules! no_op_err {
     ($op:ident, $line:ident) => {
@@ -16,59 +28,94 @@ macro_rules! no_op_err {
 
 macro_rules! f64 {
     ($dest:expr, $op:tt, $source:expr) => {
-        unsafe{ $dest = transmute::<f64, isize>(transmute::<isize, f64>($dest) $op transmute::<isize, f64>($source));}
+        $dest = to_isize(to_f64($dest) $op to_f64($source))
     };
 }
 
-macro_rules! mem_check {
-    ($self:ident, $type_len:tt, $mem:ident, $index:ident) => {
-        if $self.stack[$self.stack_size-1] >= (1<<PTR_OFFSET) {
-            let dyn_mem = &mut $self.dyn_mem[($self.stack[$self.stack_size-1]>>PTR_OFFSET)as usize-1];
-            $mem = if let Some(m) = dyn_mem {
-                if ($self.stack[$self.stack_size-1]&PTR_MASK) +$type_len > m.len() as isize {return Err(ExecErr::IllegalMemAccess); }
-                $index = 0;
-                Some(m)
-            } else { return Err(ExecErr::IllegalMemAccess); }
-        }
-        if $mem == None {
-            if $self.stack[$self.stack_size-1]+$type_len > $self.arena.len() as isize {
-                return Err(ExecErr::IllegalMemAccess);
-            }
-            $index = $self.stack[$self.stack_size-1];
-            $mem = Some(&mut $self.arena);
-        }
-        if $index < 0 { return Err(ExecErr::NativeError); }
+// comparisons follow IEEE 754, anything compared with NaN is false
+macro_rules! f64_cmp {
+    ($dest:expr, $op:tt, $source:expr) => {
+        $dest = (to_f64($dest) $op to_f64($source)) as isize
+    };
+}
+
+macro_rules! f64_fn {
+    ($dest:expr, $f:expr) => {
+        $dest = to_isize($f(to_f64($dest)))
+    };
+    ($dest:expr, $source:expr, $f:expr) => {
+        $dest = to_isize($f(to_f64($dest), to_f64($source)))
     };
 }
 
+fn to_f64(v: isize) -> f64 { f64::from_bits(v as u64) }
+fn to_isize(v: f64) -> isize { v.to_bits() as isize }
+
+// grows `v` to `len` elements, failing with the limit of `kind` instead of aborting when the allocation can't be made
+fn try_grow<T: Clone + Default>(v: &mut Vec<T>, len: usize, kind: LimitKind) -> Result<(), ExecErr> {
+    if len > v.len() {
+        v.try_reserve_exact(len - v.len()).map_err(|_| ExecErr::ResourceLimit(kind))?;
+    }
+    v.resize(len, T::default());
+    Ok(())
+}
+
+// the value is converted from bytes by $conv, by default native endian zero extended $type
 macro_rules! read_mem {
     ($self:ident, $type_len:tt, $type:tt) => {
-        let mut mem: Option<&Vec<u8>> = None;
-        let mut index: isize = -1;
-        mem_check!($self, $type_len, mem, index);
-        let index = index as usize;
-        let bytes: &[u8; $type_len] = if let Some(m) = mem { match
-            m[index..index+$type_len].try_into() {Ok(v)=>{v}
-            Err(_)=>{unreachable!()}}
-        } else {return Err(ExecErr::IllegalMemAccess);};
-        $self.stack[$self.stack_size-1] = $type::from_ne_bytes(*bytes) as isize;
+        read_mem!($self, $type_len, |b: [u8; $type_len]| $type::from_ne_bytes(b) as isize)
+    };
+    ($self:ident, $type_len:tt, $conv:expr) => {
+        let ptr = $self.stack[$self.stack_size-1];
+        if $self.sanitizer.is_some() {
+            $self.check_init(ptr, $type_len)?;
+        }
+        let bytes: [u8; $type_len] = match $self.mem_slice(ptr, $type_len)?.try_into() {
+            Ok(v) => {v}
+            Err(_) => {unreachable!()}
+        };
+        let value: isize = ($conv)(bytes);
+        $self.stack[$self.stack_size-1] = value;
+        if !$self.watchpoints.is_empty() && $self.watch(ptr, $type_len, false, value, value) {
+            $self.ip += 1;
+            return Ok(Step::Watch);
+        }
     };
 }
 
+// the value is converted to bytes by $conv, by default native endian $type
 macro_rules! write_mem {
     ($self:ident, $type_len:tt, $type:tt) => {
-        let mut mem: Option<&mut Vec<u8>> = None;
-        let mut index: isize = -1;
-        mem_check!($self, $type_len, mem, index);
-        let index = index as usize;
-        if let Some(m) = mem {
-            let bytes = ($self.stack[$self.stack_size-2] as $type).to_ne_bytes();
-            for i in 0..bytes.len() {m[index+i] = bytes[i]}
-            $self.stack_size -= 2;
+        write_mem!($self, $type_len, |v: isize| (v as $type).to_ne_bytes())
+    };
+    ($self:ident, $type_len:tt, $conv:expr) => {
+        // guard and read-only data are the first `ro_end` bytes of the arena
+        if (0..$self.ro_end as isize).contains(&$self.stack[$self.stack_size-1]) {
+            return Err(ExecErr::WriteToReadOnly);
+        }
+        let ptr = $self.stack[$self.stack_size-1];
+        let bytes: [u8; $type_len] = ($conv)($self.stack[$self.stack_size-2]);
+        let old = if $self.watchpoints.is_empty() {None} else {$self.peek(ptr, $type_len)};
+        $self.mem_slice(ptr, $type_len)?.copy_from_slice(&bytes);
+        $self.stack_size -= 2;
+        if let Some(shadow) = $self.shadow(ptr, $type_len) {
+            shadow.fill(true);
+        }
+        if let Some(old) = old {
+            let new = $self.peek(ptr, $type_len).unwrap_or(old);
+            if $self.watch(ptr, $type_len, true, old, new) {
+                $self.ip += 1;
+                return Ok(Step::Watch);
+            }
         }
     };
 }
 
+// the stack seen as bytes, used when it's mapped into linear memory
+fn stack_bytes(stack: &mut [isize]) -> &mut [u8] {
+    unsafe { std::slice::from_raw_parts_mut(stack.as_mut_ptr() as *mut u8, stack.len()*8) }
+}
+
 pub mod inst_macro {
     #[macro_export]
     macro_rules! inst {
@@ -92,12 +139,153 @@ pub struct Lada {
     arena: Vec<u8>,
     program: Vec<Inst>,
     dyn_mem: Vec<Option<Vec<u8>>>,
+    ro_end: usize,
+    limits: Limits,
+    dyn_bytes: usize,
+    output_len: usize,
+    mem_model: MemModel,
+    heap: heap::Heap,
+    // end of the memory initialized by the program (guard, rodata, data and bss)
+    static_end: usize,
+    sanitizer: Option<sanitizer::Sanitizer>,
+    watchpoints: Vec<(watch::Watchpoint, Option<watch::WatchCallback>)>,
+    watch_hit: Option<watch::WatchHit>,
+    overflow: OverflowMode,
+    // index of the first local of the current frame, 0 outside of any frame
+    fp: usize,
+    // innermost try last
+    handlers: Vec<Handler>,
+    natives: native::NativeRegistry,
+    policy: native::Policy,
+    externs: Vec<ffi::Extern>,
+    host: Box<dyn host::Host>,
+    imports: Vec<String>,
+    // import index -> native index, filled in by link or on the first call
+    import_map: Vec<Option<usize>>,
+}
+
+// state restored when an error is caught
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handler {
+    pub addr: usize,
+    pub stack_size: usize,
+    pub fp: usize,
 }
 
+// what add, sub, mult, div, shl, shr and sar do on overflow
+// Wrap: two's complement wrapping, shift amounts are taken modulo 64
+// Check: stop with ExecErr::Overflow, also for shift amounts outside of 0..64
+// Saturate: clamp to isize::MIN/isize::MAX, shift amounts are clamped to 0..64
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowMode {
+    Wrap,
+    Check,
+    Saturate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IntOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Shl,
+    Shr,
+    Sar,
+}
+
+fn int_op(op: IntOp, mode: OverflowMode, a: isize, b: isize) -> Result<isize, ExecErr> {
+    if op == IntOp::Div && b == 0 {
+        return Err(ExecErr::DivByZero);
+    }
+    Ok(match mode {
+        OverflowMode::Wrap => match op {
+            IntOp::Add => a.wrapping_add(b),
+            IntOp::Sub => a.wrapping_sub(b),
+            IntOp::Mul => a.wrapping_mul(b),
+            IntOp::Div => a.wrapping_div(b),
+            IntOp::Shl => a.wrapping_shl(b as u32),
+            IntOp::Shr => (a as usize).wrapping_shr(b as u32) as isize,
+            IntOp::Sar => a.wrapping_shr(b as u32),
+        },
+        OverflowMode::Check => match op {
+            IntOp::Add => a.checked_add(b),
+            IntOp::Sub => a.checked_sub(b),
+            IntOp::Mul => a.checked_mul(b),
+            IntOp::Div => a.checked_div(b),
+            IntOp::Shl => u32::try_from(b).ok().and_then(|b| a.checked_shl(b)),
+            IntOp::Shr => u32::try_from(b).ok().and_then(|b| (a as usize).checked_shr(b)).map(|v| v as isize),
+            IntOp::Sar => u32::try_from(b).ok().and_then(|b| a.checked_shr(b)),
+        }.ok_or(ExecErr::Overflow)?,
+        OverflowMode::Saturate => match op {
+            IntOp::Add => a.saturating_add(b),
+            IntOp::Sub => a.saturating_sub(b),
+            IntOp::Mul => a.saturating_mul(b),
+            IntOp::Div => a.saturating_div(b),
+            IntOp::Shl => {
+                let b = b.clamp(0, 64) as u32;
+                match a.checked_shl(b) {
+                    // shifted out bits must all be copies of the sign bit
+                    Some(v) if v >> b == a => v,
+                    _ if a == 0 => 0,
+                    _ => if a < 0 {isize::MIN} else {isize::MAX},
+                }
+            }
+            IntOp::Shr => (a as usize).checked_shr(b.clamp(0, 64) as u32).unwrap_or(0) as isize,
+            IntOp::Sar => a >> b.clamp(0, 63),
+        },
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Arena,
+    // index in the dynamic memory
+    Chunk(usize),
+    Stack,
+}
+
+// Split: arena and malloc chunks are separate, chunk pointers are tagged with the chunk number
+// Linear: malloc allocates from the arena, pointers are plain offsets
+// LinearStack: like Linear, with the used part of the stack readable and writable from STACK_BASE
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemModel {
+    Split,
+    Linear,
+    LinearStack,
+}
+
+// usize::MAX means no limit
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub max_stack: usize,
+    pub max_arena: usize,
+    pub max_dyn_bytes: usize,
+    pub max_chunks: usize,
+    pub max_output: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitKind {
+    Stack,
+    Arena,
+    DynBytes,
+    Chunks,
+    Output,
+}
+
+// arena layout: [guard][rodata][data][bss][rest of the arena]
 #[derive(Debug, Clone)]
 pub struct Program {
     pub inst: Vec<Inst>,
-    pub mem: Vec<u8>,
+    pub guard: usize,
+    pub rodata: Vec<u8>,
+    pub data: Vec<u8>,
+    pub bss: usize,
+    // names of the natives called with `native name`, the operand of NATIVE_IMPORT is an index in here
+    pub imports: Vec<String>,
+    // C functions the host has to register as natives before linking
+    pub externs: Vec<ffi::Extern>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -107,58 +295,196 @@ pub struct Inst {
     pub operand: isize
 }
 
-#[allow(non_camel_case_types)]
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum InstType {
-    HALT,
-    NOP,
-    PUSH,
-    POP,
-    DUP,
-    SWAP,
-    PICK,
-    SHOVE,
-    ADD,
-    SUB,
-    MULT,
-    DIV,
-    ADDF,
-    SUBF,
-    MULTF,
-    DIVF,
-    SHL,
-    SHR,
-    AND,
-    OR,
-    XOR,
-    NOT,
-    JMP,
-    JIF,
-    EQ,
-    NEG,
-    LT,
-    GT,
-    PRINT,
-    SHOUT,
-    DUMP,
-    EMPTY,
-    IFEMPTY,
-    RET,
-    FTOI,
-    ITOF,
-    FLOOR,
-    CEIL,
-    READ_8,
-    READ_16,
-    READ_32,
-    READ_64,
-    WRITE_8,
-    WRITE_16,
-    WRITE_32,
-    WRITE_64,
-    NATIVE,
-    MALLOC,
-    FREE,
+// the opcode of an instruction is its index in the table, new instructions have to be added at the end
+// name is what the disassembler prints, operand is true if the operand is stored in the bytecode
+macro_rules! inst_table {
+    ($($kind:ident, $name:literal, $operand:literal;)*) => {
+        #[allow(non_camel_case_types)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        #[repr(u8)]
+        pub enum InstType {
+            $($kind,)*
+        }
+
+        pub const INST_TABLE: &[InstInfo] = &[
+            $(InstInfo { kind: InstType::$kind, name: $name, operand: $operand },)*
+        ];
+    };
+}
+
+pub struct InstInfo {
+    pub kind: InstType,
+    pub name: &'static str,
+    pub operand: bool,
+}
+
+inst_table! {
+    HALT,        "halt",      false;
+    NOP,         "nop",       false;
+    PUSH,        "push",      true;
+    POP,         "pop",       false;
+    DUP,         "dup",       false;
+    SWAP,        "swap",      false;
+    PICK,        "pick",      false;
+    SHOVE,       "shove",     false;
+    ADD,         "add",       false;
+    SUB,         "sub",       false;
+    MULT,        "mult",      false;
+    DIV,         "div",       false;
+    ADDF,        "addf",      false;
+    SUBF,        "subf",      false;
+    MULTF,       "multf",     false;
+    DIVF,        "divf",      false;
+    SHL,         "shl",       false;
+    SHR,         "shr",       false;
+    AND,         "and",       false;
+    OR,          "or",        false;
+    XOR,         "xor",       false;
+    NOT,         "not",       false;
+    JMP,         "jmp",       true;
+    JIF,         "jif",       true;
+    EQ,          "eq",        false;
+    NEG,         "neg",       false;
+    LT,          "lt",        false;
+    GT,          "gt",        false;
+    PRINT,       "print",     false;
+    SHOUT,       "shout",     false;
+    DUMP,        "dump",      false;
+    EMPTY,       "empty",     false;
+    IFEMPTY,     "ifempty",   false;
+    RET,         "ret",       false;
+    FTOI,        "ftoi",      false;
+    ITOF,        "itof",      false;
+    FLOOR,       "floor",     false;
+    CEIL,        "ceil",      false;
+    READ_8,      "read8",     false;
+    READ_16,     "read16",    false;
+    READ_32,     "read32",    false;
+    READ_64,     "read64",    false;
+    WRITE_8,     "write8",    false;
+    WRITE_16,    "write16",   false;
+    WRITE_32,    "write32",   false;
+    WRITE_64,    "write64",   false;
+    NATIVE,      "native",    false;
+    MALLOC,      "malloc",    false;
+    FREE,        "free",      false;
+    STACK_ADR,   "stackadr",  false;
+    LTF,         "ltf",       false;
+    GTF,         "gtf",       false;
+    EQF,         "eqf",       false;
+    LEF,         "lef",       false;
+    GEF,         "gef",       false;
+    NEGF,        "negf",      false;
+    ABSF,        "absf",      false;
+    SQRT,        "sqrt",      false;
+    EXP,         "exp",       false;
+    LN,          "ln",        false;
+    SIN,         "sin",       false;
+    COS,         "cos",       false;
+    TAN,         "tan",       false;
+    ROUND,       "round",     false;
+    TRUNC,       "trunc",     false;
+    POW,         "pow",       false;
+    ATAN2,       "atan2",     false;
+    FMOD,        "fmod",      false;
+    ADDC,        "addc",      false;
+    SUBC,        "subc",      false;
+    MULC,        "mulc",      false;
+    DIVC,        "divc",      false;
+    SHLC,        "shlc",      false;
+    SHRC,        "shrc",      false;
+    ADDS,        "adds",      false;
+    SUBS,        "subs",      false;
+    MULS,        "muls",      false;
+    DIVS,        "divs",      false;
+    SHLS,        "shls",      false;
+    SHRS,        "shrs",      false;
+    MOD,         "mod",       false;
+    REM,         "rem",       false;
+    DIVU,        "divu",      false;
+    MODU,        "modu",      false;
+    LTU,         "ltu",       false;
+    GTU,         "gtu",       false;
+    SAR,         "sar",       false;
+    SEXT_8,      "sext8",     false;
+    SEXT_16,     "sext16",    false;
+    SEXT_32,     "sext32",    false;
+    ZEXT_8,      "zext8",     false;
+    ZEXT_16,     "zext16",    false;
+    ZEXT_32,     "zext32",    false;
+    READ_8S,     "read8s",    false;
+    READ_16S,    "read16s",   false;
+    READ_32S,    "read32s",   false;
+    READ_16_LE,  "read16le",  false;
+    READ_32_LE,  "read32le",  false;
+    READ_64_LE,  "read64le",  false;
+    READ_16_BE,  "read16be",  false;
+    READ_32_BE,  "read32be",  false;
+    READ_64_BE,  "read64be",  false;
+    WRITE_16_LE, "write16le", false;
+    WRITE_32_LE, "write32le", false;
+    WRITE_64_LE, "write64le", false;
+    WRITE_16_BE, "write16be", false;
+    WRITE_32_BE, "write32be", false;
+    WRITE_64_BE, "write64be", false;
+    READ_F32,    "readf32",   false;
+    WRITE_F32,   "writef32",  false;
+    JMPS,        "jmps",      false;
+    JIFS,        "jifs",      false;
+    ENTER,       "enter",     true;
+    LEAVE,       "leave",     false;
+    LLOAD,       "lload",     true;
+    LSTORE,      "lstore",    true;
+    ALOAD,       "aload",     true;
+    ASTORE,      "astore",    true;
+    TRY,         "try",       true;
+    ENDTRY,      "endtry",    false;
+    THROW,       "throw",     false;
+    OVER,        "over",      false;
+    ROT,         "rot",       false;
+    NROT,        "-rot",      false;
+    NIP,         "nip",       false;
+    TUCK,        "tuck",      false;
+    DUP2,        "2dup",      false;
+    DROP2,       "2drop",     false;
+    SWAP2,       "2swap",     false;
+    DEPTH,       "depth",     false;
+    DROP,        "drop",      true;
+    ADDI,        "addi",      true;
+    SUBI,        "subi",      true;
+    MULI,        "muli",      true;
+    DIVI,        "divi",      true;
+    SHLI,        "shli",      true;
+    SHRI,        "shri",      true;
+    SARI,        "sari",      true;
+    ANDI,        "andi",      true;
+    ORI,         "ori",       true;
+    XORI,        "xori",      true;
+    EQI,         "eqi",       true;
+    LTI,         "lti",       true;
+    GTI,         "gti",       true;
+    PICKI,       "pick",      true;
+    SWAPI,       "swap",      true;
+    SHOVEI,      "shove",     true;
+    READ_8I,     "read8",     true;
+    READ_16I,    "read16",    true;
+    READ_32I,    "read32",    true;
+    READ_64I,    "read64",    true;
+    WRITE_8I,    "write8",    true;
+    WRITE_16I,   "write16",   true;
+    WRITE_32I,   "write32",   true;
+    WRITE_64I,   "write64",   true;
+    POPCNT,      "popcnt",    false;
+    CLZ,         "clz",       false;
+    CTZ,         "ctz",       false;
+    ROTL,        "rotl",      false;
+    ROTR,        "rotr",      false;
+    BSWAP,       "bswap",     false;
+    BTEST,       "btest",     false;
+    BSET,        "bset",      false;
+    BCLR,        "bclr",      false;
+    BEXTR,       "bextr",     false;
+    NATIVE_IMPORT, "native",  true;
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -173,7 +499,61 @@ pub enum ExecErr {
     IllegalOperand,
     Redefinition,
     IllegalMemAccess,
+    WriteToReadOnly,
     NativeError,
+    ResourceLimit(LimitKind),
+    UninitRead,
+    Overflow,
+    // throw without a handler, with the thrown value
+    Thrown(isize),
+    // endtry without a try
+    NoHandler,
+    // an import that isn't registered
+    MissingNative(String),
+    // native and the capability the policy doesn't grant
+    PermissionDenied(String, String),
+    // the host failed to write the output
+    HostIo,
+    // frame instruction outside of a frame, outside of its frame or with a corrupted saved fp
+    BadFrame,
+}
+
+// how an instruction that didn't fail ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Done,
+    // the instruction finished and a watchpoint asked to halt, see `last_watch_hit`
+    Watch,
+}
+
+impl ExecErr {
+    // value pushed for the handler, errors of the vm are negative, throw passes its value through
+    pub fn code(&self) -> isize {
+        match self {
+            ExecErr::Thrown(v) => *v,
+            ExecErr::StackOverflow => -1,
+            ExecErr::StackUnderflow => -2,
+            ExecErr::IllegalInst => -3,
+            ExecErr::DivByZero => -4,
+            ExecErr::NoOperand => -5,
+            ExecErr::IllegalAddr => -6,
+            ExecErr::IllegalInstAddr => -7,
+            ExecErr::IllegalOperand => -8,
+            ExecErr::Redefinition => -9,
+            ExecErr::IllegalMemAccess => -10,
+            ExecErr::WriteToReadOnly => -11,
+            ExecErr::NativeError => -12,
+            ExecErr::ResourceLimit(_) => -13,
+            ExecErr::UninitRead => -14,
+            ExecErr::Overflow => -15,
+            // -16 was the watchpoint stop, reserved so handlers and plugins keep seeing the same codes
+            ExecErr::BadFrame => -17,
+            ExecErr::NoHandler => -18,
+            ExecErr::MissingNative(_) => -19,
+            ExecErr::PermissionDenied(..) => -20,
+            ExecErr::HostIo => -21,
+        }
+    }
 }
 
 pub enum PrintType {
@@ -182,67 +562,453 @@ pub enum PrintType {
     HEX
 }
 
-type Native = fn(&mut Lada) -> Result<(), ExecErr>;
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_stack: usize::MAX,
+            max_arena: usize::MAX,
+            max_dyn_bytes: usize::MAX,
+            max_chunks: usize::MAX,
+            max_output: usize::MAX,
+        }
+    }
+}
 
 impl Lada {
     pub fn init(program: Program, stack_cap: usize, arena_size: usize) -> Lada {
-        let mut arena = program.mem;
-        if arena_size > arena.len() {arena.resize(arena_size, 0);}
-        Lada {
+        Lada::init_with_model(program, stack_cap, arena_size, MemModel::Split)
+    }
+
+    pub fn init_with_model(program: Program, stack_cap: usize, arena_size: usize, mem_model: MemModel) -> Lada {
+        match Lada::init_with_limits(program, stack_cap, arena_size, mem_model, Limits::default()) {
+            Ok(vm) => vm,
+            Err(e) => panic!("can't allocate the vm memory: {e:?}"),
+        }
+    }
+
+    // the sizes are checked against the limits before anything is allocated
+    pub fn init_with_limits(program: Program, stack_cap: usize, arena_size: usize, mem_model: MemModel, limits: Limits) -> Result<Lada, ExecErr> {
+        if stack_cap > limits.max_stack {
+            return Err(ExecErr::ResourceLimit(LimitKind::Stack));
+        }
+        let ro_end = program.guard + program.rodata.len();
+        let static_end = (ro_end + program.data.len()).checked_add(program.bss)
+            .ok_or(ExecErr::ResourceLimit(LimitKind::Arena))?;
+        if static_end.max(arena_size) > limits.max_arena {
+            return Err(ExecErr::ResourceLimit(LimitKind::Arena));
+        }
+        let mut arena = vec![0; program.guard];
+        arena.extend(program.rodata);
+        arena.extend(program.data);
+        try_grow(&mut arena, static_end.max(arena_size), LimitKind::Arena)?;
+        let mut stack = vec![];
+        try_grow(&mut stack, stack_cap, LimitKind::Stack)?;
+        Ok(Lada {
             halted: false,
             ip: 0,
             stack_size: 0,
-            stack: vec![0; stack_cap],
+            stack,
             arena,
             program: program.inst,
             // because 0<<48 == zero chunk addresses will be offset by 1
             dyn_mem: vec![],
-        }
+            ro_end,
+            limits,
+            dyn_bytes: 0,
+            output_len: 0,
+            mem_model,
+            heap: heap::Heap::default(),
+            static_end,
+            sanitizer: None,
+            watchpoints: vec![],
+            watch_hit: None,
+            overflow: OverflowMode::Wrap,
+            fp: 0,
+            handlers: vec![],
+            natives: linux::builtins(),
+            policy: native::Policy::allow_all(),
+            import_map: vec![None; program.imports.len()],
+            imports: program.imports,
+            externs: program.externs,
+            host: Box::new(host::StdHost::new()),
+        })
     }
 
     pub fn ip(&self) -> usize {self.ip}
     pub fn halted(&self) -> bool {self.halted}
     pub fn inst(&self, n: usize) -> &Inst {&self.program[n]}
     pub fn prog_len(&self) -> usize {self.program.len()}
-    pub fn stack_extend(&mut self, n: usize) { self.stack.resize(self.stack.len()+n, 0); }
+    pub fn natives(&self) -> &native::NativeRegistry {&self.natives}
+    pub fn natives_mut(&mut self) -> &mut native::NativeRegistry {&mut self.natives}
+    pub fn imports(&self) -> &[String] {&self.imports}
+    pub fn externs(&self) -> &[ffi::Extern] {&self.externs}
+    pub fn host(&mut self) -> &mut dyn host::Host {self.host.as_mut()}
+    // returns the previous host, flushed
+    pub fn set_host(&mut self, host: Box<dyn host::Host>) -> Box<dyn host::Host> {
+        let _ = self.flush();
+        std::mem::replace(&mut self.host, host)
+    }
+    // the default host buffers stdout, flush before writing to it around the vm
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.host.stdout().flush()?;
+        self.host.stderr().flush()
+    }
+    pub fn policy(&self) -> &native::Policy {&self.policy}
+    pub fn set_policy(&mut self, policy: native::Policy) {self.policy = policy}
+
+    pub fn register_native(&mut self, name: &str, capability: &str, arity: usize,
+                           func: impl FnMut(&mut native::NativeCtx) -> Result<(), ExecErr> + 'static) -> usize {
+        self.natives.register(name, capability, arity, func)
+    }
+
+    // resolves every import, call it after registering natives to find missing ones before running
+    pub fn link(&mut self) -> Result<(), ExecErr> {
+        for i in 0..self.imports.len() {
+            self.resolve_import(i)?;
+        }
+        Ok(())
+    }
+
+    fn resolve_import(&mut self, i: usize) -> Result<usize, ExecErr> {
+        if let Some(Some(n)) = self.import_map.get(i) {
+            return Ok(*n);
+        }
+        let name = self.imports.get(i).ok_or(ExecErr::IllegalOperand)?;
+        let n = self.natives.get(name).ok_or_else(|| ExecErr::MissingNative(name.clone()))?;
+        self.import_map[i] = Some(n);
+        Ok(n)
+    }
+
+    pub fn native(&mut self, n: usize) -> Result<(), ExecErr> {
+        // the registry is moved out so the native can borrow the vm
+        let mut natives = std::mem::take(&mut self.natives);
+        let res = natives.call(n, self);
+        self.natives = natives;
+        res
+    }
+    pub fn stack_extend(&mut self, n: usize) -> Result<(), ExecErr> {
+        if self.stack.len().saturating_add(n) > self.limits.max_stack {
+            return Err(ExecErr::ResourceLimit(LimitKind::Stack));
+        }
+        let len = self.stack.len()+n;
+        try_grow(&mut self.stack, len, LimitKind::Stack)
+    }
     pub fn get_arena(&self) -> &[u8] {&self.arena}
-    pub fn resize_arena(&mut self, n: usize) { self.arena.resize(n, 0); }
+    pub fn ro_end(&self) -> usize {self.ro_end}
+    pub fn resize_arena(&mut self, n: usize) -> Result<(), ExecErr> {
+        if n > self.limits.max_arena {
+            return Err(ExecErr::ResourceLimit(LimitKind::Arena));
+        }
+        // the heap is at the end of linear memory, it can't be cut off
+        let n = if self.mem_model == MemModel::Split {n} else {n.max(self.heap.end())};
+        try_grow(&mut self.arena, n, LimitKind::Arena)?;
+        if let Some(san) = &mut self.sanitizer { san.resize_arena(n); }
+        Ok(())
+    }
+    // copies the arguments after the end of the arena, each followed by a zero byte, then an array of their
+    // addresses ending with 0, and pushes argc and the address of the array like C's main gets them
+    pub fn set_args(&mut self, args: &[String]) -> Result<(), ExecErr> {
+        if self.stack_size+2 > self.stack.len() {
+            return Err(ExecErr::StackOverflow);
+        }
+        let start = self.arena.len().div_ceil(8)*8;
+        let argv = start.saturating_add(args.iter().map(|a| a.len()+1).sum()).div_ceil(8)*8;
+        let end = argv.saturating_add((args.len()+1)*8);
+        self.resize_arena(end)?;
+        let mut adr = start;
+        for (i, arg) in args.iter().enumerate() {
+            self.arena[adr..adr+arg.len()].copy_from_slice(arg.as_bytes());
+            self.arena[argv+i*8..argv+i*8+8].copy_from_slice(&adr.to_ne_bytes());
+            adr += arg.len()+1;
+        }
+        if let Some(shadow) = self.shadow(start as isize, end-start) {
+            shadow.fill(true);
+        }
+        self.stack[self.stack_size] = args.len() as isize;
+        self.stack[self.stack_size+1] = argv as isize;
+        self.stack_size += 2;
+        Ok(())
+    }
     pub fn last_err_inst(&self) -> &InstType { &self.program[self.ip].kind }
     pub fn get_stack_top(&self, n: usize) -> &[isize] { &self.stack[self.stack_size-n..self.stack_size] }
     pub fn get_dyn_mem(&self) -> &[Option<Vec<u8>>] {&self.dyn_mem}
+    pub fn get_heap(&self) -> &heap::Heap {&self.heap}
+    pub fn mem_model(&self) -> MemModel {self.mem_model}
+    fn live_chunks(&self) -> usize {
+        match self.mem_model {
+            MemModel::Split => self.dyn_mem.iter().flatten().count(),
+            _ => self.heap.blocks().len(),
+        }
+    }
+    pub fn limits(&self) -> &Limits {&self.limits}
+
+    // fails if the vm already uses more than the new limits allow
+    pub fn set_limits(&mut self, limits: Limits) -> Result<(), ExecErr> {
+        if self.stack.len() > limits.max_stack { return Err(ExecErr::ResourceLimit(LimitKind::Stack)); }
+        if self.arena.len() > limits.max_arena { return Err(ExecErr::ResourceLimit(LimitKind::Arena)); }
+        if self.dyn_bytes > limits.max_dyn_bytes { return Err(ExecErr::ResourceLimit(LimitKind::DynBytes)); }
+        if self.live_chunks() > limits.max_chunks { return Err(ExecErr::ResourceLimit(LimitKind::Chunks)); }
+        if self.output_len > limits.max_output { return Err(ExecErr::ResourceLimit(LimitKind::Output)); }
+        self.limits = limits;
+        Ok(())
+    }
+
+    // tracks which bytes were written, memory outside of the program's static data starts uninitialized
+    pub fn set_sanitizer(&mut self, mode: Option<sanitizer::SanitizeMode>) {
+        self.sanitizer = mode.map(|mode| sanitizer::Sanitizer::new(mode, self.arena.len(), self.static_end, &self.dyn_mem));
+    }
+
+    pub fn take_sanitizer_reports(&mut self) -> Vec<sanitizer::UninitRead> {
+        match &mut self.sanitizer {
+            Some(san) => std::mem::take(&mut san.reports),
+            None => vec![],
+        }
+    }
+
+    // shadow bytes of a pointer, None if the memory is always initialized or out of bounds
+    fn shadow(&mut self, ptr: isize, len: usize) -> Option<&mut [bool]> {
+        self.sanitizer.as_ref()?;
+        let (region, index) = self.region(ptr)?;
+        let san = self.sanitizer.as_mut()?;
+        let shadow = match region {
+            Region::Arena => &mut san.arena,
+            Region::Chunk(n) => san.dyn_mem.get_mut(n)?,
+            Region::Stack => return None,
+        };
+        shadow.get_mut(index..index+len)
+    }
+
+    fn check_init(&mut self, ptr: isize, len: usize) -> Result<(), ExecErr> {
+        let ip = self.ip;
+        let Some(shadow) = self.shadow(ptr, len) else { return Ok(()) };
+        if shadow.iter().all(|b| *b) { return Ok(()); }
+        // report every byte only once
+        shadow.fill(true);
+        let Some(san) = &mut self.sanitizer else { return Ok(()) };
+        san.reports.push(sanitizer::UninitRead { ip, addr: ptr, len });
+        match san.mode {
+            sanitizer::SanitizeMode::Warn => Ok(()),
+            sanitizer::SanitizeMode::Error => Err(ExecErr::UninitRead),
+        }
+    }
+
+    // the `len` bytes at `ptr` in whichever memory it points to, used by the memory instructions and natives
+    pub(crate) fn mem_slice(&mut self, ptr: isize, len: usize) -> Result<&mut [u8], ExecErr> {
+        if self.mem_model == MemModel::LinearStack && ptr >= STACK_BASE {
+            // only the used part of the stack is addressable
+            let index = (ptr-STACK_BASE) as usize;
+            if index.checked_add(len).is_none_or(|end| end > self.stack_size*8) {
+                return Err(ExecErr::IllegalMemAccess);
+            }
+            return Ok(&mut stack_bytes(&mut self.stack)[index..index+len]);
+        }
+        if self.mem_model == MemModel::Split && ptr >= (1<<PTR_OFFSET) {
+            let Some(Some(m)) = self.dyn_mem.get_mut((ptr>>PTR_OFFSET) as usize-1) else {
+                return Err(ExecErr::IllegalMemAccess);
+            };
+            let index = (ptr&PTR_MASK) as usize;
+            if index.checked_add(len).is_none_or(|end| end > m.len()) {
+                return Err(ExecErr::IllegalMemAccess);
+            }
+            return Ok(&mut m[index..index+len]);
+        }
+        if ptr < 0 {
+            return Err(ExecErr::NativeError);
+        }
+        let index = ptr as usize;
+        if index.checked_add(len).is_none_or(|end| end > self.arena.len()) {
+            return Err(ExecErr::IllegalMemAccess);
+        }
+        Ok(&mut self.arena[index..index+len])
+    }
 
-    pub fn print_stack(&self, t: &PrintType) {
-        print!("[");
-        if self.stack_size == 0 {
-            println!("]");
+    // which memory a pointer points to and the offset in it, doesn't check the upper bound
+    pub fn region(&self, ptr: isize) -> Option<(Region, usize)> {
+        if ptr < 0 { return None; }
+        if self.mem_model == MemModel::LinearStack && ptr >= STACK_BASE {
+            Some((Region::Stack, (ptr-STACK_BASE) as usize))
+        } else if self.mem_model == MemModel::Split && ptr >= (1<<PTR_OFFSET) {
+            Some((Region::Chunk((ptr>>PTR_OFFSET) as usize-1), (ptr&PTR_MASK) as usize))
         } else {
-            match t {
-                PrintType::I64 => {
-                    for i in 0..self.stack_size-1 {
-                        print!("{}, ", self.stack[i]);
-                    }   println!("{}]", self.stack[self.stack_size-1]);
-                }
-                PrintType::F64 => { unsafe {
-                    for i in 0..self.stack_size-1 {
-                        print!("{:.7e}, ",  transmute::<isize, f64>(self.stack[i]));
-                    }   println!("{:.7e}]", transmute::<isize, f64>(self.stack[self.stack_size-1]));
-                }}
-                PrintType::HEX => {
-                    for i in 0..self.stack_size-1 {
-                        print!("{:X}, ", self.stack[i]);
-                    }   println!("{:X}]", self.stack[self.stack_size-1]);
-                }
+            Some((Region::Arena, ptr as usize))
+        }
+    }
+
+    // zero extended value of `len` bytes at `ptr`, None if it's out of bounds
+    fn peek(&self, ptr: isize, len: usize) -> Option<isize> {
+        let (region, index) = self.region(ptr)?;
+        let mem: &[u8] = match region {
+            Region::Arena => &self.arena,
+            Region::Chunk(n) => self.dyn_mem.get(n)?.as_ref()?,
+            Region::Stack => unsafe { std::slice::from_raw_parts(self.stack.as_ptr() as *const u8, self.stack_size*8) },
+        };
+        let mut bytes = [0u8; 8];
+        bytes[..len].copy_from_slice(mem.get(index..index+len)?);
+        Some(isize::from_ne_bytes(bytes))
+    }
+
+    // halts by default, with a callback it halts only if the callback returns true
+    pub fn add_watchpoint(&mut self, wp: watch::Watchpoint, callback: Option<watch::WatchCallback>) -> usize {
+        self.watchpoints.push((wp, callback));
+        self.watchpoints.len()-1
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+        self.watch_hit = None;
+    }
+
+    pub fn last_watch_hit(&self) -> Option<&watch::WatchHit> {self.watch_hit.as_ref()}
+
+    // returns true if the vm should halt after the current instruction
+    fn watch(&mut self, ptr: isize, len: usize, write: bool, old: isize, new: isize) -> bool {
+        let Some((region, index)) = self.region(ptr) else { return false };
+        let mut halt = false;
+        for (id, (wp, callback)) in self.watchpoints.iter_mut().enumerate() {
+            if !wp.matches(&region, index, len, write) { continue; }
+            let hit = watch::WatchHit { id, ip: self.ip, addr: ptr, write, old, new };
+            if callback.as_mut().is_none_or(|c| c(&hit)) {
+                halt = true;
+                self.watch_hit = Some(hit);
             }
         }
+        halt
+    }
+
+    pub fn fp(&self) -> usize {self.fp}
+    pub fn handlers(&self) -> &[Handler] {&self.handlers}
+    // frame pointers from the innermost frame out, each frame starts with the saved fp at fp-1
+    pub fn frames(&self) -> Vec<usize> {
+        let mut frames = vec![];
+        let mut fp = self.fp;
+        while fp > 0 && fp <= self.stack_size && !frames.contains(&fp) {
+            frames.push(fp);
+            fp = self.stack[fp-1] as usize;
+        }
+        frames
+    }
+
+    pub fn frames_str(&self, t: &PrintType) -> String {
+        let mut end = self.stack_size;
+        let frames: Vec<String> = self.frames().iter().map(|&fp| {
+            let values: Vec<String> = self.stack[fp..end.max(fp)].iter().map(|v| match t {
+                PrintType::I64 => format!("{}", v),
+                PrintType::F64 => format!("{:.7e}", to_f64(*v)),
+                PrintType::HEX => format!("{:X}", v),
+            }).collect();
+            end = fp-1;
+            format!("{fp}: [{}]", values.join(", "))
+        }).collect();
+        frames.join(" <- ")
     }
 
-    pub fn exec_inst(&mut self, print_type: &PrintType) -> Result<(), ExecErr> {
+    // checks that there are `inputs` values on the stack and room to replace them with `outputs` values
+    fn stack_effect(&self, inputs: usize, outputs: usize) -> Result<(), ExecErr> {
+        if self.stack_size < inputs {
+            return Err(ExecErr::StackUnderflow)
+        }
+        if self.stack_size - inputs + outputs > self.stack.len() {
+            return Err(ExecErr::StackOverflow)
+        }
+        Ok(())
+    }
+
+    // stack index of the local or argument an instruction refers to
+    fn frame_slot(&self, local: bool, i: isize) -> Result<usize, ExecErr> {
+        if self.fp == 0 || i < 0 {
+            return Err(ExecErr::BadFrame);
+        }
+        let slot = if local {
+            (self.fp as isize).checked_add(i)
+        } else {
+            // the saved fp and the return address are below the frame, arguments are below them
+            (self.fp as isize - 3).checked_sub(i)
+        };
+        match slot {
+            Some(slot) if slot >= 0 && (slot as usize) < self.stack_size => Ok(slot as usize),
+            _ => Err(ExecErr::BadFrame),
+        }
+    }
+
+    pub fn overflow_mode(&self) -> OverflowMode {self.overflow}
+    pub fn set_overflow_mode(&mut self, mode: OverflowMode) {self.overflow = mode;}
+
+    // integer operation on the two top values
+    fn arith(&mut self, op: IntOp, mode: OverflowMode) -> Result<(), ExecErr> {
+        if self.stack_size < 2 {
+            return Err(ExecErr::StackUnderflow)
+        }
+        self.stack[self.stack_size-2] = int_op(op, mode, self.stack[self.stack_size-2], self.stack[self.stack_size-1])?;
+        self.stack_size -= 1;
+        Ok(())
+    }
+
+    // all the output of the program goes through here to count towards the limit
+    fn output(&mut self, s: &str) -> Result<(), ExecErr> {
+        if self.output_len.saturating_add(s.len()) > self.limits.max_output {
+            return Err(ExecErr::ResourceLimit(LimitKind::Output));
+        }
+        self.output_len += s.len();
+        self.host.stdout().write_all(s.as_bytes()).map_err(|_| ExecErr::HostIo)
+    }
+
+    pub fn print_stack(&mut self, t: &PrintType) {
+        let stack = self.stack_str(t);
+        let _ = writeln!(self.host.stdout(), "{stack}");
+    }
+
+    pub fn stack_str(&self, t: &PrintType) -> String {
+        let values: Vec<String> = self.stack[..self.stack_size].iter().map(|v| match t {
+            PrintType::I64 => format!("{}", v),
+            PrintType::F64 => format!("{:.7e}", to_f64(*v)),
+            PrintType::HEX => format!("{:X}", v),
+        }).collect();
+        format!("[{}]", values.join(", "))
+    }
+
+    // errors inside of a try are caught here, sanitizer errors, resource limits and denied permissions are left to the host
+    pub fn exec_inst(&mut self, print_type: &PrintType) -> Result<Step, ExecErr> {
+        let stack_size = self.stack_size;
+        let res = self.step(print_type);
+        if res.is_err() && self.program.get(self.ip).is_some_and(|i| i.kind.pushed_operand_base().is_some()) {
+            // a failed immediate form leaves the stack as it was, so it can be retried
+            self.stack_size = stack_size;
+        }
+        let e = match res {
+            Ok(step) => return Ok(step),
+            Err(e @ (ExecErr::UninitRead | ExecErr::ResourceLimit(_) | ExecErr::PermissionDenied(..))) => return Err(e),
+            Err(e) => e,
+        };
+        let Some(handler) = self.handlers.pop() else {return Err(e)};
+        self.stack_size = self.stack_size.min(handler.stack_size);
+        if self.stack_size >= self.stack.len() {
+            return Err(e);
+        }
+        self.stack[self.stack_size] = e.code();
+        self.stack_size += 1;
+        self.fp = handler.fp;
+        self.ip = handler.addr;
+        Ok(Step::Done)
+    }
+
+    fn step(&mut self, print_type: &PrintType) -> Result<Step, ExecErr> {
         if self.ip >= self.program.len() {
             return Err(ExecErr::IllegalInstAddr)
         }
 
         let inst = &self.program[self.ip];
-        match inst.kind {
+        let kind = match inst.kind.pushed_operand_base() {
+            Some(base) => {
+                if self.stack_size >= self.stack.len() {
+                    return Err(ExecErr::StackOverflow)
+                }
+                self.stack[self.stack_size] = inst.operand;
+                self.stack_size += 1;
+                base
+            }
+            None => inst.kind,
+        };
+        match kind {
             InstType::NOP => {}
             InstType::PUSH => {
                 if self.stack_size >= self.stack.len() {
@@ -271,17 +1037,22 @@ impl Lada {
             }
 
             InstType::SWAP => {
-                if self.stack[self.stack_size-1] < 0 || self.stack[self.stack_size-1] >= self.stack_size as isize {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                // the depth doesn't count itself
+                if self.stack[self.stack_size-1] < 0 || self.stack[self.stack_size-1] >= self.stack_size as isize - 1 {
                     return Err(ExecErr::IllegalAddr);
                 }
                 self.stack_size -=1;
                 let adr = self.stack[self.stack_size];
-                let tmp = self.stack[self.stack_size-1];
-                self.stack[self.stack_size-1] = self.stack[self.stack_size -1 -adr as usize];
-                self.stack[self.stack_size -1 -adr as usize] = tmp;
+                self.stack.swap(self.stack_size-1, self.stack_size -1 -adr as usize);
             }
 
             InstType::PICK => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                }
                 if self.stack[self.stack_size-1] < 0 || self.stack[self.stack_size-1] >= self.stack_size as isize {
                     return Err(ExecErr::IllegalAddr);
                 }
@@ -289,6 +1060,9 @@ impl Lada {
             }
 
             InstType::SHOVE => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                }
                 if self.stack[self.stack_size-1] < 0 || self.stack[self.stack_size-1] >= self.stack_size as isize -1 {
                     return Err(ExecErr::IllegalAddr);
                 }
@@ -297,236 +1071,663 @@ impl Lada {
                 self.stack_size -= 2;
             }
 
-            InstType::ADD => {
+            InstType::ADD => self.arith(IntOp::Add, self.overflow)?,
+
+            // immediate forms, the operand is the value that would be on top of the stack
+            InstType::ADDI | InstType::SUBI | InstType::MULI | InstType::DIVI |
+            InstType::SHLI | InstType::SHRI | InstType::SARI => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                let op = match kind {
+                    InstType::ADDI => IntOp::Add,
+                    InstType::SUBI => IntOp::Sub,
+                    InstType::MULI => IntOp::Mul,
+                    InstType::DIVI => IntOp::Div,
+                    InstType::SHLI => IntOp::Shl,
+                    InstType::SHRI => IntOp::Shr,
+                    _ => IntOp::Sar,
+                };
+                self.stack[self.stack_size-1] = int_op(op, self.overflow, self.stack[self.stack_size-1], inst.operand)?;
+            }
+
+            InstType::ANDI | InstType::ORI | InstType::XORI | InstType::EQI | InstType::LTI | InstType::GTI => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                let (a, b) = (self.stack[self.stack_size-1], inst.operand);
+                self.stack[self.stack_size-1] = match kind {
+                    InstType::ANDI => a & b,
+                    InstType::ORI => a | b,
+                    InstType::XORI => a ^ b,
+                    InstType::EQI => (a == b) as isize,
+                    InstType::LTI => (a < b) as isize,
+                    _ => (a > b) as isize,
+                };
+            }
+
+            InstType::PICKI | InstType::SWAPI | InstType::SHOVEI |
+            InstType::READ_8I | InstType::READ_16I | InstType::READ_32I | InstType::READ_64I |
+            InstType::WRITE_8I | InstType::WRITE_16I | InstType::WRITE_32I | InstType::WRITE_64I => unreachable!(),
+
+            InstType::SUB => self.arith(IntOp::Sub, self.overflow)?,
+
+            InstType::MULT => self.arith(IntOp::Mul, self.overflow)?,
+
+            InstType::DIV => self.arith(IntOp::Div, self.overflow)?,
+            InstType::ADDC => self.arith(IntOp::Add, OverflowMode::Check)?,
+            InstType::SUBC => self.arith(IntOp::Sub, OverflowMode::Check)?,
+            InstType::MULC => self.arith(IntOp::Mul, OverflowMode::Check)?,
+            InstType::DIVC => self.arith(IntOp::Div, OverflowMode::Check)?,
+            InstType::SHLC => self.arith(IntOp::Shl, OverflowMode::Check)?,
+            InstType::SHRC => self.arith(IntOp::Shr, OverflowMode::Check)?,
+            InstType::ADDS => self.arith(IntOp::Add, OverflowMode::Saturate)?,
+            InstType::SUBS => self.arith(IntOp::Sub, OverflowMode::Saturate)?,
+            InstType::MULS => self.arith(IntOp::Mul, OverflowMode::Saturate)?,
+            InstType::DIVS => self.arith(IntOp::Div, OverflowMode::Saturate)?,
+            InstType::SHLS => self.arith(IntOp::Shl, OverflowMode::Saturate)?,
+            InstType::SHRS => self.arith(IntOp::Shr, OverflowMode::Saturate)?,
+
+            InstType::ADDF => {
                 if self.stack_size < 2 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                self.stack[self.stack_size-2] += self.stack[self.stack_size-1];
+                f64!(self.stack[self.stack_size-2], +, self.stack[self.stack_size-1]);
                 self.stack_size -= 1;
             }
 
-            InstType::SUB => {
+            InstType::SUBF => {
                 if self.stack_size < 2 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                self.stack[self.stack_size-2] -= self.stack[self.stack_size-1];
+                f64!(self.stack[self.stack_size-2], -, self.stack[self.stack_size-1]);
                 self.stack_size -= 1;
             }
 
-            InstType::MULT => {
+            InstType::MULTF => {
                 if self.stack_size < 2 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                self.stack[self.stack_size-2] *= self.stack[self.stack_size-1];
+                f64!(self.stack[self.stack_size-2], *, self.stack[self.stack_size-1]);
                 self.stack_size -= 1;
             }
 
-            InstType::DIV => {
+            InstType::DIVF => {
                 if self.stack_size < 2 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                if self.stack[self.stack_size-1] == 0 {
+                f64!(self.stack[self.stack_size-2], /, self.stack[self.stack_size-1]);
+                self.stack_size -= 1;
+            }
+
+            InstType::SHL => self.arith(IntOp::Shl, self.overflow)?,
+            InstType::SHR => self.arith(IntOp::Shr, self.overflow)?,
+            InstType::SAR => self.arith(IntOp::Sar, self.overflow)?,
+
+            InstType::REM | InstType::MOD | InstType::DIVU | InstType::MODU => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                let a = self.stack[self.stack_size-2];
+                let b = self.stack[self.stack_size-1];
+                if b == 0 {
                     return Err(ExecErr::DivByZero);
                 }
-                self.stack[self.stack_size-2] /= self.stack[self.stack_size-1];
+                self.stack[self.stack_size-2] = match inst.kind {
+                    // sign of the dividend
+                    InstType::REM => a.wrapping_rem(b),
+                    // sign of the divisor
+                    InstType::MOD => {
+                        let r = a.wrapping_rem(b);
+                        if r != 0 && (r < 0) != (b < 0) {r + b} else {r}
+                    }
+                    InstType::DIVU => ((a as usize) / (b as usize)) as isize,
+                    _ => ((a as usize) % (b as usize)) as isize,
+                };
+                self.stack_size -= 1;
+            }
+
+            InstType::SEXT_8 | InstType::SEXT_16 | InstType::SEXT_32 |
+            InstType::ZEXT_8 | InstType::ZEXT_16 | InstType::ZEXT_32 => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                let v = self.stack[self.stack_size-1];
+                self.stack[self.stack_size-1] = match inst.kind {
+                    InstType::SEXT_8 => v as i8 as isize,
+                    InstType::SEXT_16 => v as i16 as isize,
+                    InstType::SEXT_32 => v as i32 as isize,
+                    InstType::ZEXT_8 => v as u8 as isize,
+                    InstType::ZEXT_16 => v as u16 as isize,
+                    _ => v as u32 as isize,
+                };
+            }
+            InstType::AND => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                self.stack[self.stack_size-2] &= self.stack[self.stack_size-1];
+                self.stack_size -= 1;
+            }
+            InstType::OR => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                self.stack[self.stack_size-2] |= self.stack[self.stack_size-1];
+                self.stack_size -= 1;
+            }
+            InstType::XOR => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                self.stack[self.stack_size-2] ^= self.stack[self.stack_size-1];
+                self.stack_size -= 1;
+            }
+            InstType::NOT => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                self.stack[self.stack_size-1] = !self.stack[self.stack_size-1];
+            }
+
+            InstType::POPCNT | InstType::CLZ | InstType::CTZ | InstType::BSWAP => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                let v = self.stack[self.stack_size-1];
+                self.stack[self.stack_size-1] = match kind {
+                    InstType::POPCNT => v.count_ones() as isize,
+                    InstType::CLZ => v.leading_zeros() as isize,
+                    InstType::CTZ => v.trailing_zeros() as isize,
+                    _ => v.swap_bytes(),
+                };
+            }
+
+            // value, amount - the amount is taken modulo 64
+            InstType::ROTL | InstType::ROTR => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                let (v, n) = (self.stack[self.stack_size-2], self.stack[self.stack_size-1].rem_euclid(64) as u32);
+                self.stack[self.stack_size-2] = if kind == InstType::ROTL {v.rotate_left(n)} else {v.rotate_right(n)};
+                self.stack_size -= 1;
+            }
+
+            // value, bit index 0..64
+            InstType::BTEST | InstType::BSET | InstType::BCLR => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                let (v, bit) = (self.stack[self.stack_size-2], self.stack[self.stack_size-1]);
+                if !(0..64).contains(&bit) {
+                    return Err(ExecErr::IllegalOperand)
+                }
+                self.stack[self.stack_size-2] = match kind {
+                    InstType::BTEST => (v >> bit) & 1,
+                    InstType::BSET => v | (1 << bit),
+                    _ => v & !(1 << bit),
+                };
+                self.stack_size -= 1;
+            }
+
+            // value, start, length - zero extended bits start..start+length
+            InstType::BEXTR => {
+                if self.stack_size < 3 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                let v = self.stack[self.stack_size-3] as usize;
+                let (start, len) = (self.stack[self.stack_size-2], self.stack[self.stack_size-1]);
+                if start < 0 || len < 0 || start > 64 || len > 64 - start {
+                    return Err(ExecErr::IllegalOperand)
+                }
+                let mask = usize::MAX.checked_shr(64 - len as u32).unwrap_or(0);
+                self.stack[self.stack_size-3] = (v.checked_shr(start as u32).unwrap_or(0) & mask) as isize;
+                self.stack_size -= 2;
+            }
+
+            InstType::JMP => {
+                if inst.operand < 0 || inst.operand as usize >= self.program.len() {
+                    return Err(ExecErr::IllegalInstAddr);
+                }
+                self.ip = inst.operand as usize;
+                return Ok(Step::Done)
+            }
+
+            // saves fp and reserves zeroed locals
+            InstType::ENTER => {
+                let n = inst.operand;
+                if n < 0 {
+                    return Err(ExecErr::IllegalOperand);
+                }
+                if self.stack_size.checked_add(n as usize + 1).is_none_or(|end| end > self.stack.len()) {
+                    return Err(ExecErr::StackOverflow)
+                }
+                self.stack[self.stack_size] = self.fp as isize;
+                self.stack_size += 1;
+                self.fp = self.stack_size;
+                self.stack[self.stack_size..self.stack_size + n as usize].fill(0);
+                self.stack_size += n as usize;
+            }
+
+            // drops the locals and everything above them and restores fp
+            InstType::LEAVE => {
+                if self.fp == 0 || self.fp > self.stack_size {
+                    return Err(ExecErr::BadFrame);
+                }
+                let saved = self.stack[self.fp-1];
+                if saved < 0 || saved as usize >= self.fp {
+                    return Err(ExecErr::BadFrame);
+                }
+                self.stack_size = self.fp-1;
+                self.fp = saved as usize;
+            }
+
+            InstType::LLOAD | InstType::ALOAD => {
+                if self.stack_size >= self.stack.len() {
+                    return Err(ExecErr::StackOverflow)
+                }
+                let slot = self.frame_slot(inst.kind == InstType::LLOAD, inst.operand)?;
+                self.stack[self.stack_size] = self.stack[slot];
+                self.stack_size += 1;
+            }
+
+            InstType::LSTORE | InstType::ASTORE => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                let slot = self.frame_slot(inst.kind == InstType::LSTORE, inst.operand)?;
+                if slot == self.stack_size-1 {
+                    return Err(ExecErr::BadFrame);
+                }
+                self.stack[slot] = self.stack[self.stack_size-1];
+                self.stack_size -= 1;
+            }
+
+            InstType::TRY => {
+                if inst.operand < 0 || inst.operand as usize >= self.program.len() {
+                    return Err(ExecErr::IllegalInstAddr);
+                }
+                self.handlers.push(Handler { addr: inst.operand as usize, stack_size: self.stack_size, fp: self.fp });
+            }
+
+            InstType::ENDTRY => {
+                if self.handlers.pop().is_none() {
+                    return Err(ExecErr::NoHandler);
+                }
+            }
+
+            InstType::THROW => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                self.stack_size -= 1;
+                return Err(ExecErr::Thrown(self.stack[self.stack_size]));
+            }
+
+            InstType::JMPS => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                let adr = self.stack[self.stack_size-1];
+                if adr < 0 || adr as usize >= self.program.len() {
+                    return Err(ExecErr::IllegalInstAddr);
+                }
+                self.stack_size -= 1;
+                self.ip = adr as usize;
+                return Ok(Step::Done)
+            }
+
+            // condition, address
+            InstType::JIFS => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                let adr = self.stack[self.stack_size-1];
+                if adr < 0 || adr as usize >= self.program.len() {
+                    return Err(ExecErr::IllegalInstAddr);
+                }
+                self.stack_size -= 2;
+                if self.stack[self.stack_size] != 0 {
+                    self.ip = adr as usize;
+                    return Ok(Step::Done)
+                }
+            }
+
+            InstType::JIF => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                if inst.operand < 0 || inst.operand as usize >= self.program.len() {
+                    return Err(ExecErr::IllegalInstAddr);
+                }
+                if self.stack[self.stack_size-1] != 0 {
+                    self.stack_size -= 1;
+                    self.ip = inst.operand as usize;
+                    return Ok(Step::Done)
+                }
+                self.stack_size -= 1;
+            }
+
+            InstType::EQ => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                self.stack[self.stack_size-2] = (self.stack[self.stack_size-2] == self.stack[self.stack_size-1]) as isize;
+                self.stack_size -= 1;
+            }
+
+            InstType::NEG => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                self.stack[self.stack_size-1] = (self.stack[self.stack_size-1] <= 0) as isize;
+            }
+
+            InstType::LTU => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                self.stack[self.stack_size-2] = ((self.stack[self.stack_size-2] as usize) < (self.stack[self.stack_size-1] as usize)) as isize;
+                self.stack_size -= 1;
+            }
+
+            InstType::GTU => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                self.stack[self.stack_size-2] = ((self.stack[self.stack_size-2] as usize) > (self.stack[self.stack_size-1] as usize)) as isize;
+                self.stack_size -= 1;
+            }
+
+            InstType::LT => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                self.stack[self.stack_size-2] = (self.stack[self.stack_size-2] < self.stack[self.stack_size-1]) as isize;
+                self.stack_size -= 1;
+            }
+
+            InstType::GT => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                self.stack[self.stack_size-2] = (self.stack[self.stack_size-2] > self.stack[self.stack_size-1]) as isize;
+                self.stack_size -= 1;
+            }
+
+            InstType::PRINT => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                let v = self.stack[self.stack_size-1];
+                self.output(&format!("{v} | {v:X} | {f:.7e}\n", f=to_f64(v)))?;
+            }
+
+            InstType::SHOUT => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                }
+                let v = self.stack[self.stack_size-1];
+                self.output(&format!("{v} | {v:X} | {f:.7e}\n", f=to_f64(v)))?;
+                self.stack_size -= 1;
+            }
+
+            InstType::DUMP => {
+                let stack = self.stack_str(print_type);
+                self.output(&format!("Stack: {stack}\n"))?;
+            }
+
+            InstType::EMPTY => {
+                self.stack_size = 0;
+            }
+
+            InstType::IFEMPTY => {
+                if self.stack_size >= self.stack.len() {
+                    return Err(ExecErr::StackOverflow)
+                }
+                self.stack[self.stack_size] = (self.stack_size == 0) as isize;
+                self.stack_size += 1;
+            }
+
+            // forth stack words, stack effects in forth notation
+            // ( a b -- a b a )
+            InstType::OVER => {
+                self.stack_effect(2, 3)?;
+                self.stack[self.stack_size] = self.stack[self.stack_size-2];
+                self.stack_size += 1;
+            }
+
+            // ( a b c -- b c a )
+            InstType::ROT => {
+                self.stack_effect(3, 3)?;
+                self.stack[self.stack_size-3..self.stack_size].rotate_left(1);
+            }
+
+            // ( a b c -- c a b )
+            InstType::NROT => {
+                self.stack_effect(3, 3)?;
+                self.stack[self.stack_size-3..self.stack_size].rotate_right(1);
+            }
+
+            // ( a b -- b )
+            InstType::NIP => {
+                self.stack_effect(2, 1)?;
+                self.stack[self.stack_size-2] = self.stack[self.stack_size-1];
+                self.stack_size -= 1;
+            }
+
+            // ( a b -- b a b )
+            InstType::TUCK => {
+                self.stack_effect(2, 3)?;
+                let (a, b) = (self.stack[self.stack_size-2], self.stack[self.stack_size-1]);
+                self.stack[self.stack_size-2..self.stack_size+1].copy_from_slice(&[b, a, b]);
+                self.stack_size += 1;
+            }
+
+            // ( a b -- a b a b )
+            InstType::DUP2 => {
+                self.stack_effect(2, 4)?;
+                self.stack.copy_within(self.stack_size-2..self.stack_size, self.stack_size);
+                self.stack_size += 2;
+            }
+
+            // ( a b -- )
+            InstType::DROP2 => {
+                self.stack_effect(2, 0)?;
+                self.stack_size -= 2;
+            }
+
+            // ( a b c d -- c d a b )
+            InstType::SWAP2 => {
+                self.stack_effect(4, 4)?;
+                self.stack[self.stack_size-4..self.stack_size].rotate_left(2);
+            }
+
+            // ( -- n ) n is the number of values before the push
+            InstType::DEPTH => {
+                self.stack_effect(0, 1)?;
+                self.stack[self.stack_size] = self.stack_size as isize;
+                self.stack_size += 1;
+            }
+
+            // drops operand number of values
+            InstType::DROP => {
+                if inst.operand < 0 {
+                    return Err(ExecErr::IllegalOperand)
+                }
+                self.stack_effect(inst.operand as usize, 0)?;
+                self.stack_size -= inst.operand as usize;
+            }
+
+            InstType::RET => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow);
+                }
+                self.ip = self.stack[self.stack_size-1] as usize;
                 self.stack_size -= 1;
+                self.ip += 1;
             }
 
-            InstType::ADDF => {
-                if self.stack_size < 2 {
+            InstType::FTOI => {
+                if self.stack_size < 1 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                f64!(self.stack[self.stack_size-2], +, self.stack[self.stack_size-1]);
-                self.stack_size -= 1;
+                self.stack[self.stack_size-1] = to_f64(self.stack[self.stack_size-1]) as isize;
             }
 
-            InstType::SUBF => {
-                if self.stack_size < 2 {
+            InstType::ITOF => {
+                if self.stack_size < 1 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                f64!(self.stack[self.stack_size-2], -, self.stack[self.stack_size-1]);
-                self.stack_size -= 1;
+                self.stack[self.stack_size-1] = to_isize(self.stack[self.stack_size-1] as f64);
             }
 
-            InstType::MULTF => {
-                if self.stack_size < 2 {
+            InstType::FLOOR => {
+                if self.stack_size < 1 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                f64!(self.stack[self.stack_size-2], *, self.stack[self.stack_size-1]);
-                self.stack_size -= 1;
+                self.stack[self.stack_size-1] = to_isize(to_f64(self.stack[self.stack_size-1]).floor());
             }
 
-            InstType::DIVF => {
-                if self.stack_size < 2 {
+            InstType::CEIL => {
+                if self.stack_size < 1 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                f64!(self.stack[self.stack_size-2], /, self.stack[self.stack_size-1]);
-                self.stack_size -= 1;
+                self.stack[self.stack_size-1] = to_isize(to_f64(self.stack[self.stack_size-1]).ceil());
             }
 
-            InstType::SHL => {
+            InstType::LTF => {
                 if self.stack_size < 2 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                self.stack[self.stack_size-2] <<= self.stack[self.stack_size-1];
+                f64_cmp!(self.stack[self.stack_size-2], <, self.stack[self.stack_size-1]);
                 self.stack_size -= 1;
             }
-            InstType::SHR => {
+
+            InstType::GTF => {
                 if self.stack_size < 2 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                self.stack[self.stack_size-2] >>= self.stack[self.stack_size-1];
+                f64_cmp!(self.stack[self.stack_size-2], >, self.stack[self.stack_size-1]);
                 self.stack_size -= 1;
             }
-            InstType::AND => {
+
+            InstType::EQF => {
                 if self.stack_size < 2 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                self.stack[self.stack_size-2] &= self.stack[self.stack_size-1];
+                f64_cmp!(self.stack[self.stack_size-2], ==, self.stack[self.stack_size-1]);
                 self.stack_size -= 1;
             }
-            InstType::OR => {
+
+            InstType::LEF => {
                 if self.stack_size < 2 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                self.stack[self.stack_size-2] |= self.stack[self.stack_size-1];
+                f64_cmp!(self.stack[self.stack_size-2], <=, self.stack[self.stack_size-1]);
                 self.stack_size -= 1;
             }
-            InstType::XOR => {
+
+            InstType::GEF => {
                 if self.stack_size < 2 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                self.stack[self.stack_size-2] ^= self.stack[self.stack_size-1];
+                f64_cmp!(self.stack[self.stack_size-2], >=, self.stack[self.stack_size-1]);
                 self.stack_size -= 1;
             }
-            InstType::NOT => {
+
+            InstType::NEGF => {
                 if self.stack_size < 1 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                self.stack[self.stack_size-1] = !self.stack[self.stack_size-1];
-            }
-
-            InstType::JMP => {
-                if inst.operand < 0 || inst.operand as usize >= self.program.len() {
-                    return Err(ExecErr::IllegalInstAddr);
-                }
-                self.ip = inst.operand as usize;
-                return Ok(())
+                f64_fn!(self.stack[self.stack_size-1], |x: f64| -x);
             }
 
-            InstType::JIF => {
+            InstType::ABSF => {
                 if self.stack_size < 1 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                if inst.operand < 0 || inst.operand as usize >= self.program.len() {
-                    return Err(ExecErr::IllegalInstAddr);
-                }
-                if self.stack[self.stack_size-1] != 0 {
-                    self.stack_size -= 1;
-                    self.ip = inst.operand as usize;
-                    return Ok(())
-                }
-                self.stack_size -= 1;
+                f64_fn!(self.stack[self.stack_size-1], f64::abs);
             }
 
-            InstType::EQ => {
-                if self.stack_size < 2 {
+            InstType::SQRT => {
+                if self.stack_size < 1 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                self.stack[self.stack_size-2] = (self.stack[self.stack_size-2] == self.stack[self.stack_size-1]) as isize;
-                self.stack_size -= 1;
+                f64_fn!(self.stack[self.stack_size-1], f64::sqrt);
             }
 
-            InstType::NEG => {
+            InstType::EXP => {
                 if self.stack_size < 1 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                self.stack[self.stack_size-1] = !(self.stack[self.stack_size-1] > 0) as isize;
+                f64_fn!(self.stack[self.stack_size-1], f64::exp);
             }
 
-            InstType::LT => {
-                if self.stack_size < 2 {
+            InstType::LN => {
+                if self.stack_size < 1 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                self.stack[self.stack_size-2] = (self.stack[self.stack_size-2] < self.stack[self.stack_size-1]) as isize;
-                self.stack_size -= 1;
+                f64_fn!(self.stack[self.stack_size-1], f64::ln);
             }
 
-            InstType::GT => {
-                if self.stack_size < 2 {
+            InstType::SIN => {
+                if self.stack_size < 1 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                self.stack[self.stack_size-2] = (self.stack[self.stack_size-2] > self.stack[self.stack_size-1]) as isize;
-                self.stack_size -= 1;
+                f64_fn!(self.stack[self.stack_size-1], f64::sin);
             }
 
-            InstType::PRINT => {
+            InstType::COS => {
                 if self.stack_size < 1 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                unsafe { println!("{i} | {i:X} | {f:.7e}", i=self.stack[self.stack_size-1], f=transmute::<isize, f64>(self.stack[self.stack_size-1])); }
+                f64_fn!(self.stack[self.stack_size-1], f64::cos);
             }
 
-            InstType::SHOUT => {
+            InstType::TAN => {
                 if self.stack_size < 1 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                unsafe { println!("{i} | {i:X} | {f:.7e}", i=self.stack[self.stack_size-1], f=transmute::<isize, f64>(self.stack[self.stack_size-1])); }
-                self.stack_size -= 1;
-            }
-
-            InstType::DUMP => {
-                print!("Stack: ");
-                self.print_stack(&print_type);
-            }
-
-            InstType::EMPTY => {
-                self.stack_size = 0;
-            }
-
-            InstType::IFEMPTY => {
-                if self.stack_size == 0 {
-                    self.stack[0] = 1;
-                } else {
-                    self.stack[self.stack_size] = 0;
-                }
+                f64_fn!(self.stack[self.stack_size-1], f64::tan);
             }
 
-            InstType::RET => {
+            InstType::ROUND => {
                 if self.stack_size < 1 {
-                    return Err(ExecErr::StackUnderflow);
+                    return Err(ExecErr::StackUnderflow)
                 }
-                self.ip = self.stack[self.stack_size-1] as usize;
-                self.stack_size -= 1;
-                self.ip += 1;
+                f64_fn!(self.stack[self.stack_size-1], f64::round);
             }
 
-            InstType::FTOI => {
+            InstType::TRUNC => {
                 if self.stack_size < 1 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                self.stack[self.stack_size-1] = unsafe {transmute::<isize, f64>(self.stack[self.stack_size-1]) as isize};
+                f64_fn!(self.stack[self.stack_size-1], f64::trunc);
             }
 
-            InstType::ITOF => {
-                if self.stack_size < 1 {
+            InstType::POW => {
+                if self.stack_size < 2 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                self.stack[self.stack_size-1] = unsafe {transmute::<f64, isize>(self.stack[self.stack_size-1] as f64)};
+                f64_fn!(self.stack[self.stack_size-2], self.stack[self.stack_size-1], f64::powf);
+                self.stack_size -= 1;
             }
 
-            InstType::FLOOR => {
-                if self.stack_size < 1 {
+            InstType::ATAN2 => {
+                if self.stack_size < 2 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                self.stack[self.stack_size-1] = unsafe {transmute::<f64, isize>(transmute::<isize, f64>(self.stack[self.stack_size-1]).floor())};
+                f64_fn!(self.stack[self.stack_size-2], self.stack[self.stack_size-1], f64::atan2);
+                self.stack_size -= 1;
             }
 
-            InstType::CEIL => {
-                if self.stack_size < 1 {
+            InstType::FMOD => {
+                if self.stack_size < 2 {
                     return Err(ExecErr::StackUnderflow)
                 }
-                self.stack[self.stack_size-1] = unsafe {transmute::<f64, isize>(transmute::<isize, f64>(self.stack[self.stack_size-1]).ceil())};
+                f64!(self.stack[self.stack_size-2], %, self.stack[self.stack_size-1]);
+                self.stack_size -= 1;
             }
 
             InstType::READ_8 => {
@@ -570,6 +1771,92 @@ impl Lada {
                     return Err(ExecErr::StackUnderflow)
                 } write_mem!(self, 8, u64);
             }
+            InstType::READ_8S => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                } read_mem!(self, 1, i8);
+            }
+            InstType::READ_16S => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                } read_mem!(self, 2, i16);
+            }
+            InstType::READ_32S => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                } read_mem!(self, 4, i32);
+            }
+            InstType::READ_16_LE => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                } read_mem!(self, 2, |b| u16::from_le_bytes(b) as isize);
+            }
+            InstType::READ_32_LE => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                } read_mem!(self, 4, |b| u32::from_le_bytes(b) as isize);
+            }
+            InstType::READ_64_LE => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                } read_mem!(self, 8, |b| u64::from_le_bytes(b) as isize);
+            }
+            InstType::READ_16_BE => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                } read_mem!(self, 2, |b| u16::from_be_bytes(b) as isize);
+            }
+            InstType::READ_32_BE => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                } read_mem!(self, 4, |b| u32::from_be_bytes(b) as isize);
+            }
+            InstType::READ_64_BE => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                } read_mem!(self, 8, |b| u64::from_be_bytes(b) as isize);
+            }
+            InstType::WRITE_16_LE => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                } write_mem!(self, 2, |v| (v as u16).to_le_bytes());
+            }
+            InstType::WRITE_32_LE => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                } write_mem!(self, 4, |v| (v as u32).to_le_bytes());
+            }
+            InstType::WRITE_64_LE => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                } write_mem!(self, 8, |v| (v as u64).to_le_bytes());
+            }
+            InstType::WRITE_16_BE => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                } write_mem!(self, 2, |v| (v as u16).to_be_bytes());
+            }
+            InstType::WRITE_32_BE => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                } write_mem!(self, 4, |v| (v as u32).to_be_bytes());
+            }
+            InstType::WRITE_64_BE => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                } write_mem!(self, 8, |v| (v as u64).to_be_bytes());
+            }
+            // f32 in memory, f64 on the stack
+            InstType::READ_F32 => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow)
+                } read_mem!(self, 4, |b| to_isize(f32::from_ne_bytes(b) as f64));
+            }
+            InstType::WRITE_F32 => {
+                if self.stack_size < 2 {
+                    return Err(ExecErr::StackUnderflow)
+                } write_mem!(self, 4, |v| (to_f64(v) as f32).to_ne_bytes());
+            }
 
             InstType::NATIVE => {
                 if self.stack_size < 1 {
@@ -582,34 +1869,103 @@ impl Lada {
                 }
             }
 
+            InstType::NATIVE_IMPORT => {
+                let n = self.resolve_import(inst.operand as usize)?;
+                self.native(n)?;
+            }
+
             InstType::MALLOC => {
-                let mut found = false;
-                let mut adr: isize = -1;
-                for i in 0..self.dyn_mem.len() {
-                    if self.dyn_mem[i] == None {
-                        self.dyn_mem[i] = Some(vec![0;self.stack[self.stack_size-1]as usize]);
-                        found = true;
-                        adr = (i+1 << PTR_OFFSET)as isize;
-                        break
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow);
+                }
+                let size = self.stack[self.stack_size-1];
+                if !(0..=PTR_MASK).contains(&size) {
+                    return Err(ExecErr::IllegalOperand);
+                }
+                let size = size as usize;
+                // the heap of the linear models hands out aligned blocks, those are what gets charged
+                let charged = match self.mem_model {
+                    MemModel::Split => size,
+                    _ => heap::block_size(size).unwrap_or(usize::MAX),
+                };
+                if self.dyn_bytes.saturating_add(charged) > self.limits.max_dyn_bytes {
+                    return Err(ExecErr::ResourceLimit(LimitKind::DynBytes));
+                }
+                if self.mem_model != MemModel::Split {
+                    if self.live_chunks() >= self.limits.max_chunks {
+                        return Err(ExecErr::ResourceLimit(LimitKind::Chunks));
+                    }
+                    let adr = self.heap.alloc(&mut self.arena, size, self.limits.max_arena)?;
+                    let block = self.heap.blocks()[&adr];
+                    self.dyn_bytes += block;
+                    if let Some(san) = &mut self.sanitizer {
+                        san.resize_arena(self.arena.len());
+                        san.arena[adr..adr+block].fill(false);
                     }
+                    self.stack[self.stack_size-1] = adr as isize;
+                    self.ip += 1;
+                    return Ok(Step::Done);
                 }
-                if !found {
-                    adr = (self.dyn_mem.len()+1 << PTR_OFFSET)as isize;
-                    self.dyn_mem.push(Some(vec![0;self.stack[self.stack_size-1]as usize]));
+                let chunk = match self.dyn_mem.iter().position(|m| m.is_none()) {
+                    Some(i) => i,
+                    None => {
+                        self.dyn_mem.push(None);
+                        self.dyn_mem.len()-1
+                    }
+                };
+                if self.live_chunks() >= self.limits.max_chunks {
+                    return Err(ExecErr::ResourceLimit(LimitKind::Chunks));
                 }
-                if adr < 0 { return Err(ExecErr::NativeError); }
-                self.stack[self.stack_size-1] = adr;
+                let mut mem = vec![];
+                try_grow(&mut mem, size, LimitKind::DynBytes)?;
+                self.dyn_mem[chunk] = Some(mem);
+                self.dyn_bytes += size;
+                if let Some(san) = &mut self.sanitizer { san.alloc_chunk(chunk, size); }
+                self.stack[self.stack_size-1] = ((chunk+1) << PTR_OFFSET) as isize;
             }
 
             InstType::FREE => {
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow);
+                }
+                if self.mem_model != MemModel::Split {
+                    match self.heap.free(self.stack[self.stack_size-1] as usize) {
+                        Some(size) => self.dyn_bytes -= size,
+                        None => return Err(ExecErr::IllegalMemAccess)
+                    }
+                    self.stack_size -= 1;
+                    self.ip += 1;
+                    return Ok(Step::Done);
+                }
+                let chunk = (self.stack[self.stack_size-1] >> PTR_OFFSET) as usize;
+                match self.dyn_mem.get_mut(chunk.wrapping_sub(1)) {
+                    Some(m @ Some(_)) => {
+                        self.dyn_bytes -= m.as_ref().map_or(0, |m| m.len());
+                        *m = None;
+                        if let Some(san) = &mut self.sanitizer { san.free_chunk(chunk-1); }
+                    }
+                    _ => return Err(ExecErr::IllegalMemAccess)
+                }
                 self.stack_size -= 1;
-                let adr = (self.stack[self.stack_size] >> PTR_OFFSET) as usize-1;
-                self.dyn_mem[adr] = None;
+            }
+
+            InstType::STACK_ADR => {
+                if self.mem_model != MemModel::LinearStack {
+                    return Err(ExecErr::IllegalInst);
+                }
+                if self.stack_size < 1 {
+                    return Err(ExecErr::StackUnderflow);
+                }
+                if self.stack[self.stack_size-1] < 0 || self.stack[self.stack_size-1] >= self.stack_size as isize {
+                    return Err(ExecErr::IllegalAddr);
+                }
+                let slot = self.stack_size -1 -self.stack[self.stack_size-1] as usize;
+                self.stack[self.stack_size-1] = STACK_BASE + (slot*8) as isize;
             }
             InstType::HALT => self.halted = true
         }
         self.ip += 1;
-        Ok(())
+        Ok(Step::Done)
     }
 }
 
@@ -618,32 +1974,67 @@ impl fmt::Debug for Lada {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         write!(f, "Lada {{ ")?;
         write!(f, "halted: {}, ", self.halted)?;
-        write!(f, "ip: {}\n", self.ip)?;
-        write!(f, "program: [\n")?;
+        writeln!(f, "ip: {}", self.ip)?;
+        writeln!(f, "program: [")?;
         for inst in &self.program {
             write!(f, " {{ {inst} }}")?;
         }
-        write!(f, " ]\n")?;
-        write!(f, "stack size: {}\n", self.stack_size)?;
-        write!(f, "stack used: ")?;
-        self.print_stack(&PrintType::I64);
-        write!(f, "stack full: {:?}\n", self.stack)?;
-        write!(f, "arena: {:?}\n", self.arena)?;
+        writeln!(f, " ]")?;
+        writeln!(f, "stack size: {}", self.stack_size)?;
+        writeln!(f, "frame pointer: {}", self.fp)?;
+        writeln!(f, "handlers: {:?}", self.handlers)?;
+        writeln!(f, "stack used: {}", self.stack_str(&PrintType::I64))?;
+        writeln!(f, "stack full: {:?}", self.stack)?;
+        writeln!(f, "arena: {:?}", self.arena)?;
         write!(f, "dynamic memory: {:?}", self.dyn_mem)?;
         write!(f, " }}")?;
         Ok(())
     }
 }
 
+impl InstType {
+    pub fn accesses_memory(&self) -> bool {
+        matches!(self,
+            InstType::READ_8 | InstType::READ_16 | InstType::READ_32 | InstType::READ_64 |
+            InstType::WRITE_8 | InstType::WRITE_16 | InstType::WRITE_32 | InstType::WRITE_64 |
+            InstType::READ_8S | InstType::READ_16S | InstType::READ_32S |
+            InstType::READ_16_LE | InstType::READ_32_LE | InstType::READ_64_LE |
+            InstType::READ_16_BE | InstType::READ_32_BE | InstType::READ_64_BE |
+            InstType::WRITE_16_LE | InstType::WRITE_32_LE | InstType::WRITE_64_LE |
+            InstType::WRITE_16_BE | InstType::WRITE_32_BE | InstType::WRITE_64_BE |
+            InstType::READ_F32 | InstType::WRITE_F32 |
+            InstType::READ_8I | InstType::READ_16I | InstType::READ_32I | InstType::READ_64I |
+            InstType::WRITE_8I | InstType::WRITE_16I | InstType::WRITE_32I | InstType::WRITE_64I)
+    }
+
+    pub fn info(&self) -> &'static InstInfo {&INST_TABLE[*self as usize]}
+    pub fn from_byte(b: u8) -> Option<InstType> {INST_TABLE.get(b as usize).map(|i| i.kind)}
+
+    // immediate forms that run as their base instruction with the operand pushed first
+    pub fn pushed_operand_base(&self) -> Option<InstType> {
+        match self {
+            InstType::PICKI => Some(InstType::PICK),
+            InstType::SWAPI => Some(InstType::SWAP),
+            InstType::SHOVEI => Some(InstType::SHOVE),
+            InstType::READ_8I => Some(InstType::READ_8),
+            InstType::READ_16I => Some(InstType::READ_16),
+            InstType::READ_32I => Some(InstType::READ_32),
+            InstType::READ_64I => Some(InstType::READ_64),
+            InstType::WRITE_8I => Some(InstType::WRITE_8),
+            InstType::WRITE_16I => Some(InstType::WRITE_16),
+            InstType::WRITE_32I => Some(InstType::WRITE_32),
+            InstType::WRITE_64I => Some(InstType::WRITE_64),
+            _ => None,
+        }
+    }
+}
+
 impl Inst {
-    pub fn to_string(&self) -> String {
-        if self.has_op {
-            let str = format!("{}", self);
-            let (inst, op) = str.split_at(format!("{}", self).find(' ').unwrap());
-            let inst = inst.to_lowercase();
-            format!("{inst}{op}")
+    pub fn to_asm(&self) -> String {
+        if self.kind.info().operand {
+            format!("{} {}", self.kind.info().name, self.operand)
         } else {
-            format!("{}", format!("{}", self).to_lowercase())
+            self.kind.info().name.to_string()
         }
     }
 }
@@ -659,27 +2050,79 @@ impl fmt::Display for Inst {
 }
 
 pub mod file {
-    use std::{fs, mem::size_of};
+    use std::fs;
     use super::*;
 
+    // bytecode header, files without it are read as the old format (mem length + mem)
+    const MAGIC: &[u8; 4] = b"LADA";
+    const VERSION: u8 = 3;
+    // size of the protected region at address 0 reserved by the assembler
+    pub const GUARD_SIZE: usize = 8;
+    // number of instructions a jtable expands to
+    const JTABLE_LEN: isize = 12;
+
+    fn corrupted() -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "Corrupted file")
+    }
+
+    fn read_usize(buff: &[u8], i: &mut usize) -> std::io::Result<usize> {
+        let bytes = buff.get(*i..*i+8).ok_or_else(corrupted)?;
+        *i += 8;
+        Ok(usize::from_ne_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn read_section(buff: &[u8], i: &mut usize) -> std::io::Result<Vec<u8>> {
+        let len = read_usize(buff, i)?;
+        let end = i.checked_add(len).ok_or_else(corrupted)?;
+        let bytes = buff.get(*i..end).ok_or_else(corrupted)?;
+        *i = end;
+        Ok(bytes.to_vec())
+    }
+
     pub fn read_prog_from_file(source: &str) -> std::io::Result<Program> {
-        assert!(size_of::<InstType>() == size_of::<u8>(), "InstType is no longer 8bits long");
         let buff = fs::read(source)?;
-        let mut prog: Program = Program { inst: vec![], mem: vec![] };
-
-        let len = usize::from_ne_bytes(buff[..8].try_into().unwrap());
-        let mut i = 8+len;
-        for b in 8..i {
-            prog.mem.push(buff[b]);
+        let mut prog: Program = Program { inst: vec![], guard: 0, rodata: vec![], data: vec![], bss: 0, imports: vec![], externs: vec![] };
+
+        let mut i = 0;
+        if buff.starts_with(MAGIC) {
+            i += MAGIC.len();
+            // version 1 has no import table, version 2 no externs
+            let version = match buff.get(i) {
+                Some(&v) if (1..=VERSION).contains(&v) => v,
+                _ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Unsupported bytecode version")),
+            };
+            i += 1;
+            prog.guard = read_usize(&buff, &mut i)?;
+            prog.rodata = read_section(&buff, &mut i)?;
+            prog.data = read_section(&buff, &mut i)?;
+            prog.bss = read_usize(&buff, &mut i)?;
+            if version >= 2 {
+                for _ in 0..read_usize(&buff, &mut i)? {
+                    let name = read_section(&buff, &mut i)?;
+                    prog.imports.push(String::from_utf8(name).map_err(|_| corrupted())?);
+                }
+            }
+            if version >= 3 {
+                for _ in 0..read_usize(&buff, &mut i)? {
+                    let name = String::from_utf8(read_section(&buff, &mut i)?).map_err(|_| corrupted())?;
+                    let params = read_section(&buff, &mut i)?.into_iter().map(ffi::CType::from_byte).collect::<Option<Vec<_>>>();
+                    let ret = buff.get(i).and_then(|b| ffi::CType::from_byte(*b));
+                    i += 1;
+                    let (Some(params), Some(ret)) = (params, ret) else { return Err(corrupted()) };
+                    prog.externs.push(ffi::Extern { name, params, ret });
+                }
+            }
+        } else {
+            prog.data = read_section(&buff, &mut i)?;
         }
 
         while i < buff.len() {
             let mut operand = None;
-            let inst_type: InstType = unsafe {transmute(buff[i])};
+            let inst_type = InstType::from_byte(buff[i]).ok_or_else(corrupted)?;
             i += 1;
 
-            if let InstType::PUSH | InstType::JMP | InstType::JIF = inst_type {
-                assert!(i+7 < buff.len(), "Corrupted file");
+            if inst_type.info().operand {
+                if i+7 >= buff.len() { return Err(corrupted()); }
                 operand = Some(isize::from_ne_bytes(match buff[i..i+8].try_into() {
                     Ok(v) => {v}
                     Err(_) => {unreachable!()}
@@ -696,7 +2139,6 @@ pub mod file {
     }
 
     pub fn dump_prog_to_file(prog: &Program, dest: &str) -> std::io::Result<()> {
-        assert!(size_of::<InstType>() == size_of::<u8>(), "InstType is no longer 8bits long");
         std::fs::File::create(dest)?;
         match fs::OpenOptions::new().write(true).open(dest) {
             Ok(_) => {}
@@ -707,13 +2149,31 @@ pub mod file {
         }
 
         let mut f_buff: Vec<u8> = vec![];
-        f_buff.extend(usize::to_ne_bytes(prog.mem.len()).iter());
-        f_buff.extend(prog.mem.iter());
+        f_buff.extend(MAGIC);
+        f_buff.push(VERSION);
+        f_buff.extend(prog.guard.to_ne_bytes());
+        f_buff.extend(prog.rodata.len().to_ne_bytes());
+        f_buff.extend(prog.rodata.iter());
+        f_buff.extend(prog.data.len().to_ne_bytes());
+        f_buff.extend(prog.data.iter());
+        f_buff.extend(prog.bss.to_ne_bytes());
+        f_buff.extend(prog.imports.len().to_ne_bytes());
+        for name in &prog.imports {
+            f_buff.extend(name.len().to_ne_bytes());
+            f_buff.extend(name.as_bytes());
+        }
+        f_buff.extend(prog.externs.len().to_ne_bytes());
+        for ext in &prog.externs {
+            f_buff.extend(ext.name.len().to_ne_bytes());
+            f_buff.extend(ext.name.as_bytes());
+            f_buff.extend(ext.params.len().to_ne_bytes());
+            f_buff.extend(ext.params.iter().map(|p| *p as u8));
+            f_buff.push(ext.ret as u8);
+        }
         for inst in &prog.inst {
-            let byte: &u8 = unsafe {transmute(&inst.kind)};
-            f_buff.push(*byte);
+            f_buff.push(inst.kind as u8);
 
-            if inst.has_op {
+            if inst.kind.info().operand {
                 for byte in inst.operand.to_ne_bytes() {
                     f_buff.push(byte);
                 }
@@ -724,7 +2184,7 @@ pub mod file {
             Ok(_) => {Ok(())}
             Err(e) => {
                 eprintln!("Error writing to a file {dest}: {e}");
-                return Err(e);
+                Err(e)
             }
         }
     }
@@ -735,22 +2195,53 @@ pub mod file {
         addr: usize
     }
 
+    #[derive(Debug)]
+    enum Section {
+        Ro,
+        Data,
+        Bss,
+    }
+
     #[derive(Debug)]
     struct Constant<'a> {
         name: &'a str,
-        value: isize
+        value: isize,
+        // memory constants hold an offset into their section until it's placed in the arena
+        section: Option<Section>,
+    }
+
+    // operand of push and of the immediate forms: a number, $ (current instruction), &label or a constant
+    fn value(operand: &str, inst_n: isize, labels: &[Label], constants: &[Constant]) -> Result<isize, ExecErr> {
+        if let Ok(op) = operand.parse::<isize>() {
+            Ok(op)
+        } else if let Ok(op) = isize::from_str_radix(operand.trim_start_matches("0x"), 16) {
+            Ok(op)
+        } else if let Ok(op) = operand.parse::<f64>() {
+            Ok(to_isize(op))
+        } else if let "$" = operand {
+            Ok(inst_n)
+        } else if let Some(name) = operand.strip_prefix('&') {
+            labels.iter().find(|l| l.name == name).map(|l| l.addr as isize).ok_or(ExecErr::IllegalAddr)
+        } else {
+            constants.iter().find(|c| c.name == operand).map(|c| c.value).ok_or(ExecErr::IllegalOperand)
+        }
     }
 
     // will have to change or it will become a piece of spaghetti
     pub fn asm_parse(source: &str) -> Result<Program, (ExecErr, usize)> {
         let mut line_count = 0;
         let mut inst_vec: Vec<Inst> = vec![];
+        let mut rodata: Vec<u8> = vec![];
         let mut mem: Vec<u8> = vec![];
+        let mut bss: usize = 0;
         // name, operand, inst number, line
         let mut unchecked_inst_vec: Vec<(&str, &str, isize, usize)> = vec![];
         let mut inst_num: isize = 0;
         let mut label_vec: Vec<Label> = vec![];
         let mut const_vec: Vec<Constant> = vec![];
+        // rodata offsets of jump tables
+        let mut jtable_vec: Vec<usize> = vec![];
+        let mut externs: Vec<ffi::Extern> = vec![];
 
         for mut line in source.lines() {
             line_count += 1;
@@ -811,18 +2302,42 @@ pub mod file {
             }
 
             char_count = 0;
+            if let Some(decl) = line.strip_prefix("%extern ") {
+                let Some(ext) = ffi::Extern::parse(decl) else {
+                    eprintln!("Invalid extern declaration, expected %extern name (types) -> type");
+                    return Err((ExecErr::IllegalOperand, line_count));
+                };
+                if externs.iter().any(|e| e.name == ext.name) {
+                    eprintln!("Redefined extern");
+                    return Err((ExecErr::Redefinition, line_count));
+                }
+                externs.push(ext);
+                continue;
+            }
             if line.starts_with('%') || line.starts_with('@') {
                 for char in line.chars() {
                     if char == ' ' {
                         let (const_name, mut value) = line.split_at(char_count);
                         (_,value) = value.split_at(1);
+                        let section = if !line.starts_with('@') {None}
+                            else if value.starts_with('"') {Some(Section::Ro)}
+                            else if value.starts_with('[') {Some(Section::Bss)}
+                            else {Some(Section::Data)};
                         let constant = Constant{
                             name: const_name,
                             value: if line.starts_with('@') && value.starts_with('"') {
                                 let str = value.trim_matches('"').replace("\\n", "\n").replace("\\t", "\t").replace("\\0", "\0");
                                 let bytes = str.as_bytes();
-                                let adr = mem.len();
-                                mem.extend(bytes);
+                                let adr = rodata.len();
+                                rodata.extend(bytes);
+                                adr as isize
+                            } else if line.starts_with('@') && value.starts_with('[') && value.ends_with(']') {
+                                let Ok(len) = value[1..value.len()-1].trim().parse::<usize>() else {
+                                    eprintln!("Invalid size of memory reservation");
+                                    return Err((ExecErr::IllegalOperand, line_count));
+                                };
+                                let adr = bss;
+                                bss += len;
                                 adr as isize
                             } else if let Ok(v) = value.parse::<isize>() {
                                 if line.starts_with('@') {let adr = mem.len()as isize; mem.extend(v.to_ne_bytes()); adr}
@@ -838,11 +2353,12 @@ pub mod file {
                                 else {v as isize}
                             } else if let Ok(v) = value.parse::<f64>() {
                                 if line.starts_with('@') {let adr = mem.len()as isize; mem.extend(v.to_ne_bytes()); adr}
-                                else {unsafe {transmute::<f64, isize>(v)}}
+                                else {to_isize(v)}
                             } else {
                                 eprintln!("Invalid argument in macro definition");
                                 return Err((ExecErr::IllegalOperand, line_count));
-                            }
+                            },
+                            section,
                         };
 
                         for cst in &const_vec {
@@ -859,7 +2375,7 @@ pub mod file {
                 }
             }
 
-            if line.trim().len() == 0 {continue;}
+            if line.trim().is_empty() {continue;}
             char_count = 0;
             for char in line.chars() {
                 if char == ' ' {
@@ -869,63 +2385,162 @@ pub mod file {
                 }
                 char_count += 1
             }
-            if inst == "" {
+            if inst.is_empty() {
                 (inst,_) = line.split_at(line.len());
             }
 
             unchecked_inst_vec.push((inst, operand, inst_num, line_count));
-            inst_num += 1;
+            if inst == "jtable" {
+                // the table of addresses is read-only, it's filled in once all labels are known
+                jtable_vec.push(rodata.len());
+                rodata.resize(rodata.len() + operand.split_whitespace().count()*8, 0);
+                inst_num += JTABLE_LEN;
+            } else {
+                inst_num += 1;
+            }
+        }
+
+        for constant in &mut const_vec {
+            constant.value += match constant.section {
+                None => continue,
+                Some(Section::Ro) => GUARD_SIZE,
+                Some(Section::Data) => GUARD_SIZE + rodata.len(),
+                Some(Section::Bss) => GUARD_SIZE + rodata.len() + mem.len(),
+            } as isize;
+        }
+
+        // base instruction without an operand, the immediate form with one
+        macro_rules! imm {
+            ($operand:ident, $inst_n:ident, $line:ident, $base:ident, $imm:ident) => {
+                if $operand.is_empty() {
+                    inst!($base)
+                } else {
+                    imm!($operand, $inst_n, $line, $imm)
+                }
+            };
+            ($operand:ident, $inst_n:ident, $line:ident, $imm:ident) => {
+                match value($operand, $inst_n, &label_vec, &const_vec) {
+                    _ if $operand.is_empty() => return Err((ExecErr::NoOperand, $line)),
+                    Ok(v) => inst_op!($imm, v),
+                    Err(e) => return Err((e, $line))
+                }
+            };
         }
 
+        let mut imports: Vec<String> = vec![];
+        let mut jtables = jtable_vec.into_iter();
         for entry in unchecked_inst_vec {
             let operand = entry.1;
             let inst_n = entry.2;
             let line = entry.3;
+            // jumps to the label with the index from the top of the stack,
+            // continues after the table if the index is out of range
+            if entry.0 == "jtable" {
+                let table = jtables.next().unwrap_or_default();
+                let mut count = 0;
+                for (i, name) in operand.split_whitespace().enumerate() {
+                    let Some(label) = label_vec.iter().find(|l| l.name == name) else {
+                        return Err((ExecErr::IllegalAddr, line));
+                    };
+                    rodata[table+i*8..table+i*8+8].copy_from_slice(&label.addr.to_ne_bytes());
+                    count += 1;
+                }
+                inst_vec.extend([
+                    inst!(DUP),
+                    inst_op!(PUSH, count),
+                    inst!(LTU),
+                    inst_op!(JIF, inst_n+6),
+                    inst!(POP),
+                    inst_op!(JMP, inst_n+JTABLE_LEN),
+                    inst_op!(PUSH, 8),
+                    inst!(MULT),
+                    inst_op!(PUSH, (GUARD_SIZE+table) as isize),
+                    inst!(ADD),
+                    inst!(READ_64),
+                    inst!(JMPS),
+                ]);
+                continue;
+            }
             // this could be collapsed a bunch
             // todo: with a macro create an associated array/hashmap of "name" -> InstType
             inst_vec.push(
                 match entry.0 {
                     "nop" => {no_op_err!(operand, line); inst!(NOP)}
                     "push" => {
-                        if let Ok(op) = operand.parse::<isize>() {
-                            inst_op!(PUSH, op)
-                        } else if let Ok(op) = isize::from_str_radix(operand.trim_start_matches("0x"), 16) {
-                            inst_op!(PUSH, op)
-                        } else if let Ok(op) = operand.parse::<f64>() {
-                            inst_op!(PUSH, unsafe {transmute::<f64, isize>(op)})
-                        } else if let "$" = operand {
-                            inst_op!(PUSH, inst_n)
-                        } else {
-                            let mut inst = None;
-                            for constant in &const_vec {
-                                if operand == constant.name {
-                                    inst = Some(inst_op!(PUSH, constant.value)); break;
-                                }
-                            }
-                            if let Some(inst) = inst { inst }
-                            else { return Err((ExecErr::IllegalOperand, line));}
+                        match value(operand, inst_n, &label_vec, &const_vec) {
+                            Ok(v) => inst_op!(PUSH, v),
+                            Err(e) => return Err((e, line))
                         }
                     }
 
+                    "addi" => imm!(operand, inst_n, line, ADDI),
+                    "subi" => imm!(operand, inst_n, line, SUBI),
+                    "muli" => imm!(operand, inst_n, line, MULI),
+                    "divi" => imm!(operand, inst_n, line, DIVI),
+                    "shli" => imm!(operand, inst_n, line, SHLI),
+                    "shri" => imm!(operand, inst_n, line, SHRI),
+                    "sari" => imm!(operand, inst_n, line, SARI),
+                    "andi" => imm!(operand, inst_n, line, ANDI),
+                    "ori" => imm!(operand, inst_n, line, ORI),
+                    "xori" => imm!(operand, inst_n, line, XORI),
+                    "eqi" => imm!(operand, inst_n, line, EQI),
+                    "lti" => imm!(operand, inst_n, line, LTI),
+                    "gti" => imm!(operand, inst_n, line, GTI),
+
                     "pop" => {no_op_err!(operand, line); inst!(POP)}
                     "dup" => {no_op_err!(operand, line); inst!(DUP)}
-                    "swap"=> {no_op_err!(operand, line); inst!(SWAP)}
-                    "pick"=> {no_op_err!(operand, line); inst!(PICK)}
-                    "shove"=>{no_op_err!(operand, line); inst!(SHOVE)}
-                    "add" | "+" => {no_op_err!(operand, line); inst!(ADD)}
-                    "sub" | "-" => {no_op_err!(operand, line); inst!(SUB)}
-                    "mult"| "*" => {no_op_err!(operand, line); inst!(MULT)}
-                    "div" | "/" => {no_op_err!(operand, line); inst!(DIV)}
+                    "swap"=> imm!(operand, inst_n, line, SWAP, SWAPI),
+                    "pick"=> imm!(operand, inst_n, line, PICK, PICKI),
+                    "shove"=>imm!(operand, inst_n, line, SHOVE, SHOVEI),
+                    "add" | "+" => imm!(operand, inst_n, line, ADD, ADDI),
+                    "sub" | "-" => imm!(operand, inst_n, line, SUB, SUBI),
+                    "mult"| "*" => imm!(operand, inst_n, line, MULT, MULI),
+                    "div" | "/" => imm!(operand, inst_n, line, DIV, DIVI),
+                    "addc" => {no_op_err!(operand, line); inst!(ADDC)}
+                    "subc" => {no_op_err!(operand, line); inst!(SUBC)}
+                    "mulc" => {no_op_err!(operand, line); inst!(MULC)}
+                    "divc" => {no_op_err!(operand, line); inst!(DIVC)}
+                    "shlc" => {no_op_err!(operand, line); inst!(SHLC)}
+                    "shrc" => {no_op_err!(operand, line); inst!(SHRC)}
+                    "adds" => {no_op_err!(operand, line); inst!(ADDS)}
+                    "subs" => {no_op_err!(operand, line); inst!(SUBS)}
+                    "muls" => {no_op_err!(operand, line); inst!(MULS)}
+                    "divs" => {no_op_err!(operand, line); inst!(DIVS)}
+                    "shls" => {no_op_err!(operand, line); inst!(SHLS)}
+                    "shrs" => {no_op_err!(operand, line); inst!(SHRS)}
+                    "mod" => {no_op_err!(operand, line); inst!(MOD)}
+                    "rem" => {no_op_err!(operand, line); inst!(REM)}
+                    "divu" => {no_op_err!(operand, line); inst!(DIVU)}
+                    "modu" => {no_op_err!(operand, line); inst!(MODU)}
+                    "ltu" => {no_op_err!(operand, line); inst!(LTU)}
+                    "gtu" => {no_op_err!(operand, line); inst!(GTU)}
+                    "sar" => imm!(operand, inst_n, line, SAR, SARI),
+                    "sext8" => {no_op_err!(operand, line); inst!(SEXT_8)}
+                    "sext16" => {no_op_err!(operand, line); inst!(SEXT_16)}
+                    "sext32" => {no_op_err!(operand, line); inst!(SEXT_32)}
+                    "zext8" => {no_op_err!(operand, line); inst!(ZEXT_8)}
+                    "zext16" => {no_op_err!(operand, line); inst!(ZEXT_16)}
+                    "zext32" => {no_op_err!(operand, line); inst!(ZEXT_32)}
                     "addf" | "+f" => {no_op_err!(operand, line); inst!(ADDF)}
                     "subf" | "-f" => {no_op_err!(operand, line); inst!(SUBF)}
                     "multf"| "*f" => {no_op_err!(operand, line); inst!(MULTF)}
                     "divf" | "/f" => {no_op_err!(operand, line); inst!(DIVF)}
-    				"shl" | "<<" => {no_op_err!(operand, line); inst!(SHL)}
-    				"shr" | ">>" => {no_op_err!(operand, line); inst!(SHR)}
-    				"and" | "&" => {no_op_err!(operand, line); inst!(AND)}
-    				"or"  | "|" => {no_op_err!(operand, line); inst!(OR)}
-    				"xor" | "^" => {no_op_err!(operand, line); inst!(XOR)}
+    				"shl" | "<<" => imm!(operand, inst_n, line, SHL, SHLI),
+    				"shr" | ">>" => imm!(operand, inst_n, line, SHR, SHRI),
+    				"and" | "&" => imm!(operand, inst_n, line, AND, ANDI),
+    				"or"  | "|" => imm!(operand, inst_n, line, OR, ORI),
+    				"xor" | "^" => imm!(operand, inst_n, line, XOR, XORI),
     				"not" | "!" => {no_op_err!(operand, line); inst!(NOT)}
+                    "popcnt" => {no_op_err!(operand, line); inst!(POPCNT)}
+                    "clz" => {no_op_err!(operand, line); inst!(CLZ)}
+                    "ctz" => {no_op_err!(operand, line); inst!(CTZ)}
+                    "rotl" => {no_op_err!(operand, line); inst!(ROTL)}
+                    "rotr" => {no_op_err!(operand, line); inst!(ROTR)}
+                    "bswap" => {no_op_err!(operand, line); inst!(BSWAP)}
+                    "btest" => {no_op_err!(operand, line); inst!(BTEST)}
+                    "bset" => {no_op_err!(operand, line); inst!(BSET)}
+                    "bclr" => {no_op_err!(operand, line); inst!(BCLR)}
+                    "bextr" => {no_op_err!(operand, line); inst!(BEXTR)}
                     "jmp" => {
                         if let Ok(op) = operand.parse::<isize>() {
                             inst_op!(JMP, op)
@@ -941,6 +2556,54 @@ pub mod file {
                         }
                     }
 
+                    "jmps" => {no_op_err!(operand, line); inst!(JMPS)}
+                    "enter" | "lload" | "lstore" | "aload" | "astore" => {
+                        if operand.is_empty() {
+                            return Err((ExecErr::NoOperand, line));
+                        }
+                        let Ok(op) = operand.parse::<usize>() else {
+                            return Err((ExecErr::IllegalOperand, line));
+                        };
+                        match entry.0 {
+                            "enter" => inst_op!(ENTER, op as isize),
+                            "lload" => inst_op!(LLOAD, op as isize),
+                            "lstore" => inst_op!(LSTORE, op as isize),
+                            "aload" => inst_op!(ALOAD, op as isize),
+                            _ => inst_op!(ASTORE, op as isize),
+                        }
+                    }
+                    "leave" => {no_op_err!(operand, line); inst!(LEAVE)}
+                    "try" => {
+                        if let Ok(op) = operand.parse::<isize>() {
+                            inst_op!(TRY, op)
+                        } else {
+                            match label_vec.iter().find(|l| l.name == operand) {
+                                Some(label) => inst_op!(TRY, label.addr as isize),
+                                None => return Err((ExecErr::IllegalAddr, line))
+                            }
+                        }
+                    }
+                    "endtry" => {no_op_err!(operand, line); inst!(ENDTRY)}
+                    "over" => {no_op_err!(operand, line); inst!(OVER)}
+                    "rot" => {no_op_err!(operand, line); inst!(ROT)}
+                    "-rot" => {no_op_err!(operand, line); inst!(NROT)}
+                    "nip" => {no_op_err!(operand, line); inst!(NIP)}
+                    "tuck" => {no_op_err!(operand, line); inst!(TUCK)}
+                    "2dup" => {no_op_err!(operand, line); inst!(DUP2)}
+                    "2drop" => {no_op_err!(operand, line); inst!(DROP2)}
+                    "2swap" => {no_op_err!(operand, line); inst!(SWAP2)}
+                    "depth" => {no_op_err!(operand, line); inst!(DEPTH)}
+                    "drop" => {
+                        if operand.is_empty() {
+                            inst!(POP)
+                        } else if let Ok(op) = operand.parse::<usize>() {
+                            inst_op!(DROP, op as isize)
+                        } else {
+                            return Err((ExecErr::IllegalOperand, line));
+                        }
+                    }
+                    "throw" => {no_op_err!(operand, line); inst!(THROW)}
+                    "jifs" => {no_op_err!(operand, line); inst!(JIFS)}
                     "jmpif" | "jif" => {
                         if let Ok(op) = operand.parse::<isize>() {
                             inst_op!(JIF, op)
@@ -956,10 +2619,10 @@ pub mod file {
                         }
                     }
 
-                    "eq" => {no_op_err!(operand, line); inst!(EQ)}
+                    "eq" => imm!(operand, inst_n, line, EQ, EQI),
                     "neg"=> {no_op_err!(operand, line); inst!(NEG)}
-                    "lt" => {no_op_err!(operand, line); inst!(LT)}
-                    "gt" => {no_op_err!(operand, line); inst!(GT)}
+                    "lt" => imm!(operand, inst_n, line, LT, LTI),
+                    "gt" => imm!(operand, inst_n, line, GT, GTI),
                     "print" | "." => {no_op_err!(operand, line); inst!(PRINT)}
                     "shout"=> {no_op_err!(operand, line); inst!(SHOUT)}
                     "dump" => {no_op_err!(operand, line); inst!(DUMP)}
@@ -975,17 +2638,66 @@ pub mod file {
                     "itof" => {no_op_err!(operand, line); inst!(ITOF)}
                     "floor"=> {no_op_err!(operand, line); inst!(FLOOR)}
                     "ceil" => {no_op_err!(operand, line); inst!(CEIL)}
-                    "read8" => {no_op_err!(operand, line); inst!(READ_8)}
-                    "read16" => {no_op_err!(operand, line); inst!(READ_16)}
-                    "read32" => {no_op_err!(operand, line); inst!(READ_32)}
-                    "read64" => {no_op_err!(operand, line); inst!(READ_64)}
-                    "write8" => {no_op_err!(operand, line); inst!(WRITE_8)}
-                    "write16"=> {no_op_err!(operand, line); inst!(WRITE_16)}
-                    "write32"=> {no_op_err!(operand, line); inst!(WRITE_32)}
-                    "write64"=> {no_op_err!(operand, line); inst!(WRITE_64)}
-                    "native" => {no_op_err!(operand, line); inst!(NATIVE)}
+                    "ltf" | "<f" => {no_op_err!(operand, line); inst!(LTF)}
+                    "gtf" | ">f" => {no_op_err!(operand, line); inst!(GTF)}
+                    "eqf" | "=f" => {no_op_err!(operand, line); inst!(EQF)}
+                    "lef" | "<=f" => {no_op_err!(operand, line); inst!(LEF)}
+                    "gef" | ">=f" => {no_op_err!(operand, line); inst!(GEF)}
+                    "negf" => {no_op_err!(operand, line); inst!(NEGF)}
+                    "absf" => {no_op_err!(operand, line); inst!(ABSF)}
+                    "sqrt" => {no_op_err!(operand, line); inst!(SQRT)}
+                    "exp" => {no_op_err!(operand, line); inst!(EXP)}
+                    "ln" => {no_op_err!(operand, line); inst!(LN)}
+                    "sin" => {no_op_err!(operand, line); inst!(SIN)}
+                    "cos" => {no_op_err!(operand, line); inst!(COS)}
+                    "tan" => {no_op_err!(operand, line); inst!(TAN)}
+                    "round" => {no_op_err!(operand, line); inst!(ROUND)}
+                    "trunc" => {no_op_err!(operand, line); inst!(TRUNC)}
+                    "pow" => {no_op_err!(operand, line); inst!(POW)}
+                    "atan2" => {no_op_err!(operand, line); inst!(ATAN2)}
+                    "fmod" | "%f" => {no_op_err!(operand, line); inst!(FMOD)}
+                    "read8" => imm!(operand, inst_n, line, READ_8, READ_8I),
+                    "read16" => imm!(operand, inst_n, line, READ_16, READ_16I),
+                    "read32" => imm!(operand, inst_n, line, READ_32, READ_32I),
+                    "read64" => imm!(operand, inst_n, line, READ_64, READ_64I),
+                    "write8" => imm!(operand, inst_n, line, WRITE_8, WRITE_8I),
+                    "write16"=> imm!(operand, inst_n, line, WRITE_16, WRITE_16I),
+                    "write32"=> imm!(operand, inst_n, line, WRITE_32, WRITE_32I),
+                    "write64"=> imm!(operand, inst_n, line, WRITE_64, WRITE_64I),
+                    "read8s" => {no_op_err!(operand, line); inst!(READ_8S)}
+                    "read16s" => {no_op_err!(operand, line); inst!(READ_16S)}
+                    "read32s" => {no_op_err!(operand, line); inst!(READ_32S)}
+                    "read16le" => {no_op_err!(operand, line); inst!(READ_16_LE)}
+                    "read32le" => {no_op_err!(operand, line); inst!(READ_32_LE)}
+                    "read64le" => {no_op_err!(operand, line); inst!(READ_64_LE)}
+                    "read16be" => {no_op_err!(operand, line); inst!(READ_16_BE)}
+                    "read32be" => {no_op_err!(operand, line); inst!(READ_32_BE)}
+                    "read64be" => {no_op_err!(operand, line); inst!(READ_64_BE)}
+                    "write16le" => {no_op_err!(operand, line); inst!(WRITE_16_LE)}
+                    "write32le" => {no_op_err!(operand, line); inst!(WRITE_32_LE)}
+                    "write64le" => {no_op_err!(operand, line); inst!(WRITE_64_LE)}
+                    "write16be" => {no_op_err!(operand, line); inst!(WRITE_16_BE)}
+                    "write32be" => {no_op_err!(operand, line); inst!(WRITE_32_BE)}
+                    "write64be" => {no_op_err!(operand, line); inst!(WRITE_64_BE)}
+                    "readf32" => {no_op_err!(operand, line); inst!(READ_F32)}
+                    "writef32" => {no_op_err!(operand, line); inst!(WRITE_F32)}
+                    "native" => {
+                        if operand.is_empty() {
+                            inst!(NATIVE)
+                        } else {
+                            let i = match imports.iter().position(|i| i == operand) {
+                                Some(i) => i,
+                                None => {
+                                    imports.push(operand.to_string());
+                                    imports.len()-1
+                                }
+                            };
+                            inst_op!(NATIVE_IMPORT, i as isize)
+                        }
+                    }
                     "malloc" => {no_op_err!(operand, line); inst!(MALLOC)}
                     "free"   => {no_op_err!(operand, line); inst!(FREE)}
+                    "stackadr" => {no_op_err!(operand, line); inst!(STACK_ADR)}
                     "halt" => {no_op_err!(operand, line); inst!(HALT)}
                     &_ => {
                         eprintln!("Error: Illegal instruction number: {} or I forgot to include some", inst_vec.len());
@@ -995,7 +2707,7 @@ pub mod file {
             );
         }
 
-        Ok(Program { inst: inst_vec, mem })
+        Ok(Program { inst: inst_vec, guard: GUARD_SIZE, rodata, data: mem, bss, imports, externs })
     }
 }
 /* https://stackoverflow.com/questions/27859822/is-it-possible-to-have-stack-allocated-arrays-with-the-size-determined-at-runtim  -  would require speed testing
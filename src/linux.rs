@@ -1,42 +1,69 @@
-use std::thread::sleep;
+use std::time::Duration;
 
 use super::*;
+use native::{NativeRegistry, NativeCtx, STDOUT, TIME, ENV};
 
-pub const NATIVES: [Native;3] = [
-    Lada::sys_print,
-    Lada::str_print,
-    Lada::sleep,
-];
-
-impl Lada {
-    pub fn native(&mut self, n: usize) -> Result<(), ExecErr> {
-        NATIVES[n](self)
-    }
+// natives every vm starts with, their indexes are the ones `push n native` uses
+pub fn builtins() -> NativeRegistry {
+    let mut natives = NativeRegistry::new();
+    natives.register("sys_print", STDOUT, 0, sys_print);
+    natives.register("str_print", STDOUT, 2, str_print);
+    natives.register("sleep", TIME, 1, native_sleep);
+    // nanoseconds from the host's clock
+    natives.register("clock_mono", TIME, 0, |ctx| {
+        let ns = ctx.host().monotonic_ns();
+        ctx.push(ns)
+    });
+    natives.register("clock_wall", TIME, 0, |ctx| {
+        let ns = ctx.host().wall_ns();
+        ctx.push(ns)
+    });
+    natives.register("getenv", ENV, 4, getenv);
+    natives
+}
 
-    // examlpe native function
-    fn sys_print(&mut self) -> Result<(), ExecErr> {
-        println!("{:?}", self.stack);
-        Ok(())
-    }
+// examlpe native function
+fn sys_print(ctx: &mut NativeCtx) -> Result<(), ExecErr> {
+    let stack = format!("{:?}\n", ctx.vm.stack);
+    ctx.output(&stack)
+}
 
-    fn str_print(&mut self) -> Result<(), ExecErr> {
-        let len = self.stack[self.stack_size-1] as usize;
-        let adr = self.stack[self.stack_size-2] as usize;
-        self.stack_size -= 2;
-        println!("{}", match std::str::from_utf8(&self.arena[adr..adr+len]) {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("Error while parsing arena string: {e}");
-                return Err(ExecErr::NativeError);
-            }
-        });
-        Ok(())
-    }
+// address, length
+fn str_print(ctx: &mut NativeCtx) -> Result<(), ExecErr> {
+    let len = ctx.pop()?;
+    let adr = ctx.pop()?;
+    let len = usize::try_from(len).map_err(|_| ExecErr::IllegalMemAccess)?;
+    let str = match ctx.read_str(adr, len) {
+        Ok(s) => format!("{s}\n"),
+        Err(ExecErr::NativeError) => {
+            let _ = writeln!(ctx.host().stderr(), "Error while parsing arena string: invalid utf-8");
+            return Err(ExecErr::NativeError);
+        }
+        Err(e) => return Err(e),
+    };
+    ctx.output(&str)
+}
 
-    fn sleep(&mut self) -> Result<(), ExecErr> {
-        self.stack_size -= 1;
-        sleep(std::time::Duration::from_millis(self.stack[self.stack_size]as u64));
-        Ok(())
-    }
+// milliseconds, negative values don't sleep
+fn native_sleep(ctx: &mut NativeCtx) -> Result<(), ExecErr> {
+    let ms = ctx.pop()?;
+    ctx.host().sleep(Duration::from_millis(ms.max(0) as u64));
+    Ok(())
+}
 
+// name address, name length, buffer address, buffer length -> length of the value or -1 if it isn't set,
+// only the part of the value that fits is copied
+fn getenv(ctx: &mut NativeCtx) -> Result<(), ExecErr> {
+    let max = ctx.pop()?;
+    let buf = ctx.pop()?;
+    let len = ctx.pop()?;
+    let adr = ctx.pop()?;
+    let len = usize::try_from(len).map_err(|_| ExecErr::IllegalMemAccess)?;
+    let max = usize::try_from(max).map_err(|_| ExecErr::IllegalMemAccess)?;
+    let name = ctx.read_str(adr, len)?;
+    let Some(value) = ctx.host().env(&name) else {
+        return ctx.push(-1);
+    };
+    ctx.write_bytes(buf, &value.as_bytes()[..value.len().min(max)])?;
+    ctx.push(value.len() as isize)
 }
@@ -0,0 +1,227 @@
+// natives registered by the host under a name, programs call them by index or through the import table
+use std::io::BufRead;
+
+use super::{Lada, ExecErr, Region};
+use super::host::Host;
+
+// capabilities a native can declare, granting `io` also grants `io.stdout`, `io.fs.read`...
+pub const STDOUT: &str = "io.stdout";
+pub const STDIN: &str = "io.stdin";
+pub const FS_READ: &str = "io.fs.read";
+pub const FS_WRITE: &str = "io.fs.write";
+pub const TIME: &str = "time";
+pub const RANDOM: &str = "random";
+pub const PROCESS: &str = "process";
+pub const ENV: &str = "process.env";
+pub const FFI: &str = "ffi";
+
+pub type NativeFn = Box<dyn FnMut(&mut NativeCtx) -> Result<(), ExecErr>>;
+
+pub struct NativeDef {
+    pub name: String,
+    // what the program has to be granted to call the native
+    pub capability: String,
+    // number of values the native expects on the stack, checked before it's called
+    pub arity: usize,
+    func: NativeFn,
+}
+
+#[derive(Default)]
+pub struct NativeRegistry {
+    natives: Vec<NativeDef>,
+}
+
+impl NativeRegistry {
+    pub fn new() -> NativeRegistry {NativeRegistry::default()}
+
+    // returns the index of the native, registering a name again replaces the native but keeps its index
+    pub fn register(&mut self, name: &str, capability: &str, arity: usize,
+                    func: impl FnMut(&mut NativeCtx) -> Result<(), ExecErr> + 'static) -> usize {
+        let def = NativeDef { name: name.to_string(), capability: capability.to_string(), arity, func: Box::new(func) };
+        match self.get(name) {
+            Some(i) => {
+                self.natives[i] = def;
+                i
+            }
+            None => {
+                self.natives.push(def);
+                self.natives.len()-1
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<usize> {self.natives.iter().position(|n| n.name == name)}
+    pub fn natives(&self) -> &[NativeDef] {&self.natives}
+    pub fn len(&self) -> usize {self.natives.len()}
+    pub fn is_empty(&self) -> bool {self.natives.is_empty()}
+
+    pub(crate) fn call(&mut self, n: usize, vm: &mut Lada) -> Result<(), ExecErr> {
+        let Some(native) = self.natives.get_mut(n) else {
+            return Err(ExecErr::NativeError);
+        };
+        if !vm.policy.allows(&native.capability) {
+            return Err(ExecErr::PermissionDenied(native.name.clone(), native.capability.clone()));
+        }
+        if vm.stack_size < native.arity {
+            return Err(ExecErr::StackUnderflow);
+        }
+        (native.func)(&mut NativeCtx { vm, name: &native.name })
+    }
+}
+
+impl std::fmt::Debug for NativeRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.natives.iter().map(|n| (&n.name, &n.capability, n.arity))).finish()
+    }
+}
+
+// capabilities granted to a program, a vm starts with everything granted and lv with nothing
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Policy {
+    grants: Vec<String>,
+}
+
+impl Policy {
+    pub fn deny_all() -> Policy {Policy::default()}
+    pub fn allow_all() -> Policy {Policy { grants: vec!["*".to_string()] }}
+
+    pub fn allow(&mut self, capability: &str) {
+        if !self.grants.iter().any(|g| g == capability) {
+            self.grants.push(capability.to_string());
+        }
+    }
+
+    pub fn allows(&self, capability: &str) -> bool {
+        self.grants.iter().any(|g| g == "*" || g == capability ||
+            capability.strip_prefix(g.as_str()).is_some_and(|rest| rest.starts_with('.')))
+    }
+
+    pub fn grants(&self) -> &[String] {&self.grants}
+
+    // one capability per line, `#` starts a comment
+    pub fn parse(text: &str) -> Policy {
+        let mut policy = Policy::deny_all();
+        for line in text.lines() {
+            let cap = line.split('#').next().unwrap_or("").trim();
+            if !cap.is_empty() {
+                policy.allow(cap);
+            }
+        }
+        policy
+    }
+}
+
+// what a native sees of the vm
+pub struct NativeCtx<'a> {
+    pub(crate) vm: &'a mut Lada,
+    name: &'a str,
+}
+
+impl NativeCtx<'_> {
+    pub fn pop(&mut self) -> Result<isize, ExecErr> {
+        if self.vm.stack_size < 1 {
+            return Err(ExecErr::StackUnderflow);
+        }
+        self.vm.stack_size -= 1;
+        Ok(self.vm.stack[self.vm.stack_size])
+    }
+
+    pub fn push(&mut self, v: isize) -> Result<(), ExecErr> {
+        if self.vm.stack_size >= self.vm.stack.len() {
+            return Err(ExecErr::StackOverflow);
+        }
+        self.vm.stack[self.vm.stack_size] = v;
+        self.vm.stack_size += 1;
+        Ok(())
+    }
+
+    // pointers are resolved like in read8..read64, both arena and dynamic memory
+    pub fn read_bytes(&mut self, ptr: isize, len: usize) -> Result<Vec<u8>, ExecErr> {
+        let bytes = self.vm.mem_slice(ptr, len)?.to_vec();
+        if self.vm.sanitizer.is_some() {
+            self.vm.check_init(ptr, len)?;
+        }
+        Ok(bytes)
+    }
+
+    pub fn write_bytes(&mut self, ptr: isize, data: &[u8]) -> Result<(), ExecErr> {
+        self.writable(ptr, data.len())?.copy_from_slice(data);
+        if let Some(shadow) = self.vm.shadow(ptr, data.len()) {
+            shadow.fill(true);
+        }
+        Ok(())
+    }
+
+    fn writable(&mut self, ptr: isize, len: usize) -> Result<&mut [u8], ExecErr> {
+        if self.vm.region(ptr).is_some_and(|(r, i)| r == Region::Arena && i < self.vm.ro_end) {
+            return Err(ExecErr::WriteToReadOnly);
+        }
+        self.vm.mem_slice(ptr, len)
+    }
+
+    // fails like write_bytes would, for natives that have to check the destination before consuming their input
+    pub fn check_writable(&mut self, ptr: isize, len: usize) -> Result<(), ExecErr> {
+        self.writable(ptr, len).map(|_| ())
+    }
+
+    // `fill` writes straight into the len bytes at ptr and returns how many of them it wrote,
+    // the destination is checked before it runs
+    pub fn write_with<E>(&mut self, ptr: isize, len: usize, fill: impl FnOnce(&mut [u8]) -> Result<usize, E>) -> Result<Result<usize, E>, ExecErr> {
+        let res = fill(self.writable(ptr, len)?);
+        if let Ok(n) = res {
+            if let Some(shadow) = self.vm.shadow(ptr, n) {
+                shadow.fill(true);
+            }
+        }
+        Ok(res)
+    }
+
+    // host address of the vm memory at ptr, for handing it to foreign code that can use everything up to
+    // the end of the arena, chunk or stack; only valid until the vm runs again
+    pub fn host_ptr(&mut self, ptr: isize) -> Result<*mut u8, ExecErr> {
+        if self.vm.region(ptr).is_some_and(|(r, i)| r == Region::Arena && i < self.vm.ro_end) {
+            return Err(ExecErr::WriteToReadOnly);
+        }
+        self.host_const_ptr(ptr).map(|p| p as *mut u8)
+    }
+
+    // like host_ptr, also for read-only data, the foreign code must not write through it
+    pub fn host_const_ptr(&mut self, ptr: isize) -> Result<*const u8, ExecErr> {
+        let (region, index) = self.vm.region(ptr).ok_or(ExecErr::IllegalMemAccess)?;
+        let len = match region {
+            Region::Arena => self.vm.arena.len(),
+            Region::Chunk(n) => self.vm.dyn_mem.get(n).and_then(|m| m.as_ref()).map_or(0, |m| m.len()),
+            Region::Stack => self.vm.stack_size*8,
+        };
+        if index >= len {
+            return Err(ExecErr::IllegalMemAccess);
+        }
+        Ok(self.vm.mem_slice(ptr, len-index)?.as_ptr())
+    }
+
+    // the bytes have to be valid utf-8
+    pub fn read_str(&mut self, ptr: isize, len: usize) -> Result<String, ExecErr> {
+        String::from_utf8(self.read_bytes(ptr, len)?).map_err(|_| ExecErr::NativeError)
+    }
+
+    // for natives that need more than their declared capability for some of their arguments
+    pub fn require(&self, capability: &str) -> Result<(), ExecErr> {
+        if !self.vm.policy.allows(capability) {
+            return Err(ExecErr::PermissionDenied(self.name.to_string(), capability.to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn host(&mut self) -> &mut dyn Host {self.vm.host.as_mut()}
+
+    // the output is flushed first, so a prompt shows up before the program waits for input
+    pub fn stdin(&mut self) -> &mut dyn BufRead {
+        let _ = self.vm.host.stdout().flush();
+        self.vm.host.stdin()
+    }
+
+    // counts towards the output limit like PRINT
+    pub fn output(&mut self, s: &str) -> Result<(), ExecErr> {
+        self.vm.output(s)
+    }
+}
@@ -0,0 +1,185 @@
+// natives loaded from shared libraries, see include/lv_plugin.h for the C side
+//
+// a plugin exports `int32_t lv_plugin_init(lv_registrar *reg)`, calls `reg->api->register_native`
+// for every native and returns 0. natives get a `lv_ctx` whose `api` reaches the vm,
+// they return 0 or, after a failed api call, its nonzero result
+use std::{ffi::{c_char, c_int, c_void, CStr, CString}, io, path::Path, rc::Rc};
+
+use super::ExecErr;
+use super::native::{NativeCtx, NativeRegistry};
+
+// bumped when PluginApi changes in a way old plugins can't handle
+pub const PLUGIN_API_VERSION: u32 = 1;
+
+pub type PluginNative = extern "C" fn(ctx: *mut PluginCtx, data: *mut c_void) -> i32;
+
+// the function table a plugin sees, new functions are only added at the end
+#[repr(C)]
+pub struct PluginApi {
+    pub version: u32,
+    // name, capability, arity, function, data passed back to the function; 0 or -1 for a bad name
+    pub register_native: extern "C" fn(reg: *mut PluginRegistrar, name: *const c_char, capability: *const c_char,
+                                       arity: usize, func: PluginNative, data: *mut c_void) -> i32,
+    pub pop: extern "C" fn(ctx: *mut PluginCtx, out: *mut isize) -> i32,
+    pub push: extern "C" fn(ctx: *mut PluginCtx, v: isize) -> i32,
+    pub read_bytes: extern "C" fn(ctx: *mut PluginCtx, ptr: isize, buf: *mut u8, len: usize) -> i32,
+    pub write_bytes: extern "C" fn(ctx: *mut PluginCtx, ptr: isize, buf: *const u8, len: usize) -> i32,
+    // text for the output of the program, counts towards the output limit
+    pub output: extern "C" fn(ctx: *mut PluginCtx, s: *const u8, len: usize) -> i32,
+}
+
+#[repr(C)]
+pub struct PluginRegistrar {
+    pub api: *const PluginApi,
+    natives: *mut NativeRegistry,
+    lib: *const Rc<Library>,
+    names: *mut Vec<String>,
+}
+
+// only `api` is visible to the plugin
+#[repr(C)]
+pub struct PluginCtx {
+    pub api: *const PluginApi,
+    ctx: *mut c_void,
+    err: Option<ExecErr>,
+}
+
+static API: PluginApi = PluginApi {
+    version: PLUGIN_API_VERSION,
+    register_native,
+    pop,
+    push,
+    read_bytes,
+    write_bytes,
+    output,
+};
+
+#[cfg_attr(target_os = "linux", link(name = "dl"))]
+extern "C" {
+    fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlclose(handle: *mut c_void) -> c_int;
+    fn dlerror() -> *mut c_char;
+}
+
+const RTLD_NOW: c_int = 2;
+
+// closed when the last of its natives is dropped
+pub(crate) struct Library(*mut c_void);
+
+impl Library {
+    // None opens the program itself and the libraries it's linked with
+    pub(crate) fn open(path: Option<&Path>) -> io::Result<Library> {
+        let c_path = match path {
+            Some(p) => Some(CString::new(p.as_os_str().as_encoded_bytes()).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?),
+            None => None,
+        };
+        let handle = unsafe { dlopen(c_path.as_ref().map_or(std::ptr::null(), |p| p.as_ptr()), RTLD_NOW) };
+        if handle.is_null() {
+            return Err(dl_error("can't open library"));
+        }
+        Ok(Library(handle))
+    }
+
+    pub(crate) fn symbol(&self, name: &CStr) -> Option<*mut c_void> {
+        let sym = unsafe { dlsym(self.0, name.as_ptr()) };
+        (!sym.is_null()).then_some(sym)
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        unsafe { dlclose(self.0); }
+    }
+}
+
+fn dl_error(what: &str) -> io::Error {
+    let msg = unsafe {
+        let e = dlerror();
+        if e.is_null() {"unknown error".into()} else {CStr::from_ptr(e).to_string_lossy()}
+    };
+    io::Error::other(format!("{what}: {msg}"))
+}
+
+// returns the names of the natives the plugin registered
+pub fn load(natives: &mut NativeRegistry, path: &Path) -> io::Result<Vec<String>> {
+    let lib = Rc::new(Library::open(Some(path))?);
+    let Some(init) = lib.symbol(c"lv_plugin_init") else {
+        return Err(dl_error("plugin has no lv_plugin_init"));
+    };
+    let init: extern "C" fn(*mut PluginRegistrar) -> i32 = unsafe { std::mem::transmute(init) };
+    let mut names = vec![];
+    let mut reg = PluginRegistrar { api: &API, natives, lib: &lib, names: &mut names };
+    match init(&mut reg) {
+        0 => Ok(names),
+        e => Err(io::Error::other(format!("lv_plugin_init failed with {e}"))),
+    }
+}
+
+extern "C" fn register_native(reg: *mut PluginRegistrar, name: *const c_char, capability: *const c_char,
+                              arity: usize, func: PluginNative, data: *mut c_void) -> i32 {
+    let reg = unsafe { &mut *reg };
+    if name.is_null() || capability.is_null() {
+        return -1;
+    }
+    let (name, capability) = unsafe { (CStr::from_ptr(name), CStr::from_ptr(capability)) };
+    let (Ok(name), Ok(capability)) = (name.to_str(), capability.to_str()) else {
+        return -1;
+    };
+    let lib = unsafe { (*reg.lib).clone() };
+    unsafe { &mut *reg.natives }.register(name, capability, arity, move |ctx| {
+        let _lib = &lib;
+        let mut pctx = PluginCtx { api: &API, ctx: ctx as *mut NativeCtx as *mut c_void, err: None };
+        match func(&mut pctx, data) {
+            0 => Ok(()),
+            _ => Err(pctx.err.unwrap_or(ExecErr::NativeError)),
+        }
+    });
+    unsafe { &mut *reg.names }.push(name.to_string());
+    0
+}
+
+// runs f on the native context, a failure is kept for the native's return and reported as its code
+fn with_ctx(ctx: *mut PluginCtx, f: impl FnOnce(&mut NativeCtx) -> Result<(), ExecErr>) -> i32 {
+    let pctx = unsafe { &mut *ctx };
+    let native = unsafe { &mut *(pctx.ctx as *mut NativeCtx) };
+    match f(native) {
+        Ok(()) => 0,
+        Err(e) => {
+            let code = e.code() as i32;
+            pctx.err = Some(e);
+            code
+        }
+    }
+}
+
+extern "C" fn pop(ctx: *mut PluginCtx, out: *mut isize) -> i32 {
+    with_ctx(ctx, |c| {
+        let v = c.pop()?;
+        unsafe { *out = v; }
+        Ok(())
+    })
+}
+
+extern "C" fn push(ctx: *mut PluginCtx, v: isize) -> i32 {
+    with_ctx(ctx, |c| c.push(v))
+}
+
+extern "C" fn read_bytes(ctx: *mut PluginCtx, ptr: isize, buf: *mut u8, len: usize) -> i32 {
+    with_ctx(ctx, |c| {
+        let bytes = c.read_bytes(ptr, len)?;
+        unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), buf, len); }
+        Ok(())
+    })
+}
+
+extern "C" fn write_bytes(ctx: *mut PluginCtx, ptr: isize, buf: *const u8, len: usize) -> i32 {
+    with_ctx(ctx, |c| c.write_bytes(ptr, unsafe { std::slice::from_raw_parts(buf, len) }))
+}
+
+extern "C" fn output(ctx: *mut PluginCtx, s: *const u8, len: usize) -> i32 {
+    with_ctx(ctx, |c| {
+        let s = String::from_utf8_lossy(unsafe { std::slice::from_raw_parts(s, len) });
+        c.output(&s)
+    })
+}
@@ -0,0 +1,82 @@
+// xoshiro256** seeded through splitmix64, the same seed gives the same numbers everywhere
+use std::{cell::RefCell, rc::Rc};
+
+use super::{ExecErr, to_isize};
+use super::native::{NativeRegistry, RANDOM};
+
+#[derive(Debug, Clone)]
+pub struct Rng {
+    s: [u64; 4],
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        let mut x = seed;
+        let mut next = || {
+            x = x.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^ (z >> 31)
+        };
+        Rng { s: [next(), next(), next(), next()] }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.s;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    // uniform in lo..hi, None if the range is empty
+    pub fn range(&mut self, lo: isize, hi: isize) -> Option<isize> {
+        if hi <= lo { return None; }
+        let span = hi.abs_diff(lo) as u64;
+        // reject the top values that would make some results more likely
+        let zone = u64::MAX - (u64::MAX - span + 1) % span;
+        loop {
+            let v = self.next_u64();
+            if v <= zone {
+                return Some(lo.wrapping_add((v % span) as isize));
+            }
+        }
+    }
+
+    // uniform in 0..1
+    pub fn float(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// rand_int () -> any value
+// rand_range (lo, hi) -> value in lo..hi
+// rand_float () -> float in 0..1
+pub fn register(natives: &mut NativeRegistry, seed: u64) {
+    let rng = Rc::new(RefCell::new(Rng::new(seed)));
+
+    let r = rng.clone();
+    natives.register("rand_int", RANDOM, 0, move |ctx| {
+        let v = r.borrow_mut().next_u64() as isize;
+        ctx.push(v)
+    });
+
+    let r = rng.clone();
+    natives.register("rand_range", RANDOM, 2, move |ctx| {
+        let hi = ctx.pop()?;
+        let lo = ctx.pop()?;
+        let v = r.borrow_mut().range(lo, hi).ok_or(ExecErr::IllegalOperand)?;
+        ctx.push(v)
+    });
+
+    natives.register("rand_float", RANDOM, 0, move |ctx| {
+        let v = rng.borrow_mut().float();
+        ctx.push(to_isize(v))
+    });
+}
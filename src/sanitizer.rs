@@ -0,0 +1,51 @@
+// shadow memory for finding reads of memory the program never wrote, one flag per byte
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SanitizeMode {
+    Warn,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UninitRead {
+    pub ip: usize,
+    pub addr: isize,
+    pub len: usize,
+}
+
+#[derive(Debug)]
+pub struct Sanitizer {
+    pub(crate) mode: SanitizeMode,
+    pub(crate) arena: Vec<bool>,
+    // parallel to the dynamic memory chunks
+    pub(crate) dyn_mem: Vec<Vec<bool>>,
+    pub(crate) reports: Vec<UninitRead>,
+}
+
+impl Sanitizer {
+    pub(crate) fn new(mode: SanitizeMode, arena_len: usize, init_len: usize, dyn_mem: &[Option<Vec<u8>>]) -> Sanitizer {
+        let mut arena = vec![true; init_len.min(arena_len)];
+        arena.resize(arena_len, false);
+        Sanitizer {
+            mode,
+            arena,
+            // chunks allocated before the sanitizer was turned on are assumed to be initialized
+            dyn_mem: dyn_mem.iter().map(|m| vec![true; m.as_ref().map_or(0, |m| m.len())]).collect(),
+            reports: vec![],
+        }
+    }
+
+    pub(crate) fn alloc_chunk(&mut self, chunk: usize, size: usize) {
+        if self.dyn_mem.len() <= chunk { self.dyn_mem.resize(chunk+1, vec![]); }
+        self.dyn_mem[chunk] = vec![false; size];
+    }
+
+    pub(crate) fn free_chunk(&mut self, chunk: usize) {
+        if let Some(m) = self.dyn_mem.get_mut(chunk) { *m = vec![]; }
+    }
+
+    // new arena bytes are uninitialized
+    pub(crate) fn resize_arena(&mut self, len: usize) {
+        self.arena.resize(len, false);
+    }
+}
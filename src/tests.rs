@@ -14,43 +14,1073 @@ macro_rules! prog {
     };
 }
 
-#[cfg(test)]
-pub mod tests{
-    use crate::*;
-
-    #[test]
-    fn check_file_operations() {
-        let dest: &str = "prog_inst.dat";
-        let mut prog = Program {
-            inst: prog!(),
-            mem: vec![]
-        };
-        let prog_cp = prog.clone();
-
-        file::dump_prog_to_file(&mut prog, dest).unwrap();
-        let prog = file::read_prog_from_file(dest).unwrap();
-        for i in 0..prog_cp.inst.len() {
-            assert!(prog.inst[i] == prog_cp.inst[i]);
+use crate::*;
+
+fn run(source: &str) -> (Lada, Result<(), ExecErr>) {
+    run_vm(Lada::init(file::asm_parse(source).unwrap(), 32, 0))
+}
+
+// links the natives registered so far and runs until the vm halts or fails
+fn run_vm(mut vm: Lada) -> (Lada, Result<(), ExecErr>) {
+    if let Err(e) = vm.link() {
+        return (vm, Err(e));
+    }
+    while !vm.halted() {
+        if let Err(e) = vm.exec_inst(&PrintType::I64) {
+            return (vm, Err(e));
         }
     }
+    (vm, Ok(()))
+}
 
-    #[test]
-    fn check_asm_translate() {
-        let source: &str = "push 0\npush 69\ndup\npush 2\npick\nadd\n.\njmp 2\nhalt";
-        let asm_prog = file::asm_parse(source).unwrap();
-        let prog = prog!();
-        for i in 0..prog.len() {
-            assert!(prog[i] == asm_prog.inst[i]);
-        }
+#[test]
+fn check_file_operations() {
+    let dest: &str = "prog_inst.dat";
+    let prog = Program {
+        inst: prog!(),
+        guard: file::GUARD_SIZE,
+        rodata: b"hi".to_vec(),
+        data: vec![1, 2, 3],
+        bss: 16,
+        imports: vec!["str_print".to_string()],
+        externs: vec![ffi::Extern::parse("abs (i32) -> i32").unwrap()],
+    };
+    let prog_cp = prog.clone();
+
+    file::dump_prog_to_file(&prog, dest).unwrap();
+    let prog = file::read_prog_from_file(dest).unwrap();
+    for i in 0..prog_cp.inst.len() {
+        assert!(prog.inst[i] == prog_cp.inst[i]);
     }
+    assert_eq!(prog.guard, prog_cp.guard);
+    assert_eq!(prog.rodata, prog_cp.rodata);
+    assert_eq!(prog.data, prog_cp.data);
+    assert_eq!(prog.bss, prog_cp.bss);
+    assert_eq!(prog.imports, prog_cp.imports);
+    assert_eq!(prog.externs, prog_cp.externs);
+}
 
-    #[test]
-    fn check_asm_translate_comment() {
-        let source: &str = "push 0\npush 69  ;comment\ndup;___\n    ;   \npush 2\npick\nadd\n.\njmp 2\nhalt";
-        let asm_prog = file::asm_parse(source).unwrap();
-        let prog = prog!();
-        for i in 0..prog.len() {
-            assert!(prog[i] == asm_prog.inst[i]);
-        }
+#[test]
+fn check_corrupted_files() {
+    let dest = std::env::temp_dir().join(format!("lv_corrupted_{}.lb", std::process::id()));
+    let dest = dest.to_str().unwrap();
+    let prog = Program { inst: prog!(), guard: file::GUARD_SIZE, rodata: b"hi".to_vec(), data: vec![1, 2, 3], bss: 16,
+                         imports: vec!["read_int".to_string()], externs: vec![] };
+    file::dump_prog_to_file(&prog, dest).unwrap();
+    let bytes = std::fs::read(dest).unwrap();
+
+    // cut inside of every section and table
+    for len in [3, 9, 14, 20, 40, 60, bytes.len()-3] {
+        std::fs::write(dest, &bytes[..len]).unwrap();
+        assert!(file::read_prog_from_file(dest).is_err(), "truncated to {len} bytes");
+    }
+    // section lengths that don't fit in the file or overflow the offset
+    for huge in [1000usize, usize::MAX - 4] {
+        let mut bad = bytes.clone();
+        bad[13..21].copy_from_slice(&huge.to_ne_bytes());
+        std::fs::write(dest, &bad).unwrap();
+        assert!(file::read_prog_from_file(dest).is_err());
+    }
+    std::fs::remove_file(dest).unwrap();
+}
+
+#[test]
+fn check_asm_translate() {
+    let source: &str = "push 0\npush 69\ndup\npush 2\npick\nadd\n.\njmp 2\nhalt";
+    let asm_prog = file::asm_parse(source).unwrap();
+    let prog = prog!();
+    assert_eq!(prog.len(), asm_prog.inst.len());
+    for (inst, asm_inst) in prog.iter().zip(&asm_prog.inst) {
+        assert!(*inst == *asm_inst);
     }
 }
+
+#[test]
+fn check_asm_translate_comment() {
+    let source: &str = "push 0\npush 69  ;comment\ndup;___\n    ;   \npush 2\npick\nadd\n.\njmp 2\nhalt";
+    let asm_prog = file::asm_parse(source).unwrap();
+    let prog = prog!();
+    assert_eq!(prog.len(), asm_prog.inst.len());
+    for (inst, asm_inst) in prog.iter().zip(&asm_prog.inst) {
+        assert!(*inst == *asm_inst);
+    }
+}
+
+#[test]
+fn check_memory_sections() {
+    let source = "@msg \"hi\"\n@val 7\n@buf [4]\npush @msg\npush @val\npush @buf\npush 42\npush @buf\nwrite8\npush @buf\nread8\nhalt";
+    let (vm, res) = run(source);
+    assert_eq!(res, Ok(()));
+    let g = file::GUARD_SIZE as isize;
+    assert_eq!(vm.get_stack_top(4), &[g, g+2, g+10, 42]);
+    assert_eq!(vm.ro_end(), file::GUARD_SIZE+2);
+    assert_eq!(vm.get_arena().len(), file::GUARD_SIZE+2+8+4);
+}
+
+#[test]
+fn check_write_protection() {
+    let (_, res) = run("@msg \"hi\"\npush 0\npush @msg\nwrite8\nhalt");
+    assert_eq!(res, Err(ExecErr::WriteToReadOnly));
+    let (_, res) = run("push 1\npush 0\nwrite64\nhalt");
+    assert_eq!(res, Err(ExecErr::WriteToReadOnly));
+    let (_, res) = run("@val 7\npush 1\npush @val\nwrite64\nhalt");
+    assert_eq!(res, Ok(()));
+}
+
+#[test]
+fn check_resource_limits() {
+    let limited = |source: &str, limits: Limits| {
+        let mut vm = Lada::init(file::asm_parse(source).unwrap(), 4, 0);
+        vm.set_limits(limits).unwrap();
+        run_vm(vm).1
+    };
+    let res = limited("push 16\nmalloc\npush 16\nmalloc\nhalt", Limits { max_dyn_bytes: 24, ..Limits::default() });
+    assert_eq!(res, Err(ExecErr::ResourceLimit(LimitKind::DynBytes)));
+    let res = limited("push 1\nmalloc\npush 1\nmalloc\nhalt", Limits { max_chunks: 1, ..Limits::default() });
+    assert_eq!(res, Err(ExecErr::ResourceLimit(LimitKind::Chunks)));
+    let res = limited("push 1\nmalloc\nfree\npush 1\nmalloc\nhalt", Limits { max_chunks: 1, ..Limits::default() });
+    assert_eq!(res, Ok(()));
+    let res = limited("push -1\nmalloc\nhalt", Limits::default());
+    assert_eq!(res, Err(ExecErr::IllegalOperand));
+    let res = limited("push 1\nprint\nprint\nhalt", Limits { max_output: 20, ..Limits::default() });
+    assert_eq!(res, Err(ExecErr::ResourceLimit(LimitKind::Output)));
+
+    let mut vm = Lada::init(file::asm_parse("halt").unwrap(), 4, 0);
+    vm.set_limits(Limits { max_stack: 8, max_arena: 16, ..Limits::default() }).unwrap();
+    assert_eq!(vm.stack_extend(4), Ok(()));
+    assert_eq!(vm.stack_extend(1), Err(ExecErr::ResourceLimit(LimitKind::Stack)));
+    assert_eq!(vm.resize_arena(17), Err(ExecErr::ResourceLimit(LimitKind::Arena)));
+    assert_eq!(vm.set_limits(Limits { max_stack: 4, ..Limits::default() }), Err(ExecErr::ResourceLimit(LimitKind::Stack)));
+    assert_eq!(vm.set_limits(Limits::default()), Ok(()));
+    assert_eq!(vm.resize_arena(usize::MAX), Err(ExecErr::ResourceLimit(LimitKind::Arena)));
+
+    // checked before allocating, sizes that can't be allocated at all are limit errors too
+    let init = |stack_cap: usize, arena_size: usize, limits: Limits| {
+        Lada::init_with_limits(file::asm_parse("halt").unwrap(), stack_cap, arena_size, MemModel::Split, limits).err()
+    };
+    assert_eq!(init(99999999999999, 0, Limits { max_stack: 8, ..Limits::default() }), Some(ExecErr::ResourceLimit(LimitKind::Stack)));
+    assert_eq!(init(4, 99999999999999, Limits { max_arena: 8, ..Limits::default() }), Some(ExecErr::ResourceLimit(LimitKind::Arena)));
+    assert_eq!(init(1<<60, 0, Limits::default()), Some(ExecErr::ResourceLimit(LimitKind::Stack)));
+    assert_eq!(init(4, usize::MAX, Limits::default()), Some(ExecErr::ResourceLimit(LimitKind::Arena)));
+    assert_eq!(init(4, 16, Limits { max_stack: 4, max_arena: 16, ..Limits::default() }), None);
+    let res = limited("push 281474976710655\nmalloc\nhalt", Limits::default());
+    assert_eq!(res, Err(ExecErr::ResourceLimit(LimitKind::DynBytes)));
+
+    // linear blocks are rounded up to the heap alignment before the limit is checked
+    let linear = |source: &str, max_dyn_bytes: usize| {
+        let mut vm = Lada::init_with_model(file::asm_parse(source).unwrap(), 8, 0, MemModel::Linear);
+        vm.set_limits(Limits { max_dyn_bytes, ..Limits::default() }).unwrap();
+        let (vm, res) = run_vm(vm);
+        (vm.dyn_bytes, res)
+    };
+    assert_eq!(linear("push 1\nmalloc\nhalt", 8), (8, Ok(())));
+    assert_eq!(linear("push 1\nmalloc\nhalt", 7), (0, Err(ExecErr::ResourceLimit(LimitKind::DynBytes))));
+    assert_eq!(linear("push 1\nmalloc\npush 1\nmalloc\nhalt", 12), (8, Err(ExecErr::ResourceLimit(LimitKind::DynBytes))));
+}
+
+#[test]
+fn check_linear_memory() {
+    let source = "push 16\nmalloc\npush 16\nmalloc\npush 7\npush 2\npick\npush 8\nadd\nwrite64\ndup\npush 8\nadd\nread64\npush 2\npick\nfree\npush 8\nmalloc\nhalt";
+    let (vm, res) = run_vm(Lada::init_with_model(file::asm_parse(source).unwrap(), 8, 16, MemModel::Linear));
+    assert_eq!(res, Ok(()));
+    // blocks come right after the arena, the freed one gets reused
+    assert_eq!(vm.get_stack_top(4), &[16, 32, 7, 32]);
+    assert_eq!(vm.get_arena().len(), 48);
+    assert_eq!(vm.get_heap().blocks().len(), 2);
+
+    let mut vm = Lada::init_with_model(file::asm_parse("push 281474976710655\nmalloc\nhalt").unwrap(), 8, 16, MemModel::Linear);
+    vm.exec_inst(&PrintType::I64).unwrap();
+    assert_eq!(vm.exec_inst(&PrintType::I64), Err(ExecErr::ResourceLimit(LimitKind::Arena)));
+
+    let source = "push 5\npush 6\npush 2\nstackadr\nread64\npush 9\npush 4\nstackadr\nwrite64\nhalt";
+    let (vm, res) = run_vm(Lada::init_with_model(file::asm_parse(source).unwrap(), 8, 0, MemModel::LinearStack));
+    assert_eq!(res, Ok(()));
+    assert_eq!(vm.get_stack_top(3), &[9, 6, 5]);
+}
+
+#[test]
+fn check_sanitizer() {
+    let source = "@val 7\npush @val\nread64\npush 5\npush 16\nwrite8\npush 16\nread8\npush 17\nread8\nhalt";
+    let mut vm = Lada::init(file::asm_parse(source).unwrap(), 8, 24);
+    vm.set_sanitizer(Some(sanitizer::SanitizeMode::Error));
+    let (mut vm, res) = run_vm(vm);
+    assert_eq!(res, Err(ExecErr::UninitRead));
+    assert_eq!(vm.take_sanitizer_reports(), vec![sanitizer::UninitRead { ip: 8, addr: 17, len: 1 }]);
+
+    let source = "push 8\nmalloc\ndup\nread32\npop\npush 1\npush 2\npick\nwrite8\ndup\nread8\nhalt";
+    let mut vm = Lada::init(file::asm_parse(source).unwrap(), 8, 0);
+    vm.set_sanitizer(Some(sanitizer::SanitizeMode::Warn));
+    let (mut vm, res) = run_vm(vm);
+    assert_eq!(res, Ok(()));
+    let reports = vm.take_sanitizer_reports();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].ip, 3);
+}
+
+#[test]
+fn check_watchpoints() {
+    let source = "@val 7\npush 9\npush @val\nwrite64\npush @val\nread64\npush 3\npush @val\nwrite8\nhalt";
+    let mut vm = Lada::init(file::asm_parse(source).unwrap(), 8, 0);
+    let val = file::GUARD_SIZE;
+    let reads = std::rc::Rc::new(std::cell::Cell::new(0));
+    let counter = reads.clone();
+    vm.add_watchpoint(watch::Watchpoint { region: Region::Arena, start: val, len: 1, kind: watch::WatchKind::Write }, None);
+    vm.add_watchpoint(watch::Watchpoint { region: Region::Arena, start: val+4, len: 4, kind: watch::WatchKind::Read },
+        Some(Box::new(move |_| { counter.set(counter.get()+1); false })));
+
+    assert_eq!(vm.exec_inst(&PrintType::I64), Ok(Step::Done));
+    assert_eq!(vm.exec_inst(&PrintType::I64), Ok(Step::Done));
+    assert_eq!(vm.exec_inst(&PrintType::I64), Ok(Step::Watch));
+    assert_eq!(vm.last_watch_hit(), Some(&watch::WatchHit { id: 0, ip: 2, addr: val as isize, write: true, old: 7, new: 9 }));
+    assert_eq!(vm.ip(), 3);
+
+    let mut res = Ok(Step::Done);
+    while !vm.halted() && res == Ok(Step::Done) {
+        res = vm.exec_inst(&PrintType::I64);
+    }
+    assert_eq!(res, Ok(Step::Watch));
+    assert_eq!(vm.last_watch_hit().map(|h| (h.ip, h.old, h.new)), Some((7, 9, 3)));
+    assert_eq!(reads.get(), 1);
+
+    // ranges running past the end of the address space cover everything after their start
+    let all = watch::Watchpoint { region: Region::Arena, start: val, len: usize::MAX, kind: watch::WatchKind::ReadWrite };
+    assert!(all.matches(&Region::Arena, usize::MAX-4, 8, false));
+    assert!(!all.matches(&Region::Arena, 0, val, true));
+    assert!(all.matches(&Region::Arena, 0, usize::MAX, true));
+}
+
+#[test]
+fn check_float_ops() {
+    let float = |source: &str| {
+        let (vm, res) = run(&format!("{source}\nhalt"));
+        assert_eq!(res, Ok(()));
+        f64::from_bits(vm.get_stack_top(1)[0] as u64)
+    };
+    let int = |source: &str| {
+        let (vm, res) = run(&format!("{source}\nhalt"));
+        assert_eq!(res, Ok(()));
+        vm.get_stack_top(1)[0]
+    };
+    // raw bit patterns of negative floats compare the wrong way
+    assert_eq!(int("push -2.\npush -1.\nlt"), 0);
+    assert_eq!(int("push -2.\npush -1.\nltf"), 1);
+    assert_eq!(int("push -2.\npush -1.\n>f"), 0);
+    assert_eq!(int("push 1.\npush 1.\n<=f"), 1);
+    assert_eq!(int("push 1.\npush 2.\n>=f"), 0);
+    assert_eq!(int("push -0.\npush 0.\neqf"), 1);
+    // NaN isn't equal to, less or greater than anything
+    let nan = "push 0.\npush 0.\ndivf";
+    assert_eq!(int(&format!("{nan}\ndup\neqf")), 0);
+    assert_eq!(int(&format!("{nan}\npush 1.\nltf")), 0);
+    assert_eq!(int(&format!("{nan}\npush 1.\ngef")), 0);
+    assert!(float(&format!("{nan}\nsqrt")).is_nan());
+    assert!(float("push -1.\nsqrt").is_nan());
+    assert!(float("push -1.\nln").is_nan());
+
+    assert_eq!(float("push 2.5\nnegf"), -2.5);
+    assert_eq!(float("push -2.5\nabsf"), 2.5);
+    assert_eq!(float("push 9.\nsqrt"), 3.);
+    assert_eq!(float("push 2.\npush 10.\npow"), 1024.);
+    assert_eq!(float("push 0.\nexp"), 1.);
+    assert_eq!(float("push 1.\nln"), 0.);
+    assert_eq!(float("push 0.\nsin"), 0.);
+    assert_eq!(float("push 0.\ncos"), 1.);
+    assert_eq!(float("push 0.\ntan"), 0.);
+    assert_eq!(float("push 1.\npush -1.\natan2"), 3. * std::f64::consts::FRAC_PI_4);
+    // halfway cases round away from zero
+    assert_eq!(float("push -2.5\nround"), -3.);
+    assert_eq!(float("push -2.7\ntrunc"), -2.);
+    // the result has the sign of the dividend
+    assert_eq!(float("push -7.5\npush 2.\nfmod"), -1.5);
+    assert_eq!(float("push 7.5\npush -2.\n%f"), 1.5);
+}
+
+#[test]
+fn check_overflow() {
+    let int = |source: &str, mode: OverflowMode| {
+        let mut vm = Lada::init(file::asm_parse(&format!("{source}\nhalt")).unwrap(), 8, 0);
+        vm.set_overflow_mode(mode);
+        let (vm, res) = run_vm(vm);
+        res.map(|_| vm.get_stack_top(1)[0])
+    };
+    let max = "push 9223372036854775807";
+    let min = "push -9223372036854775808";
+    assert_eq!(int(&format!("{max}\npush 1\nadd"), OverflowMode::Wrap), Ok(isize::MIN));
+    assert_eq!(int(&format!("{min}\npush -1\ndiv"), OverflowMode::Wrap), Ok(isize::MIN));
+    assert_eq!(int("push 1\npush 65\nshl", OverflowMode::Wrap), Ok(2));
+    assert_eq!(int("push -8\npush -1\nsar", OverflowMode::Wrap), Ok(-1));
+    assert_eq!(int(&format!("{max}\npush 1\nadd"), OverflowMode::Check), Err(ExecErr::Overflow));
+    assert_eq!(int(&format!("{max}\npush 1\nadd"), OverflowMode::Saturate), Ok(isize::MAX));
+    assert_eq!(int(&format!("{min}\npush 2\nmult"), OverflowMode::Saturate), Ok(isize::MIN));
+
+    assert_eq!(int(&format!("{max}\npush 1\naddc"), OverflowMode::Wrap), Err(ExecErr::Overflow));
+    assert_eq!(int(&format!("{min}\npush 1\nsubc"), OverflowMode::Wrap), Err(ExecErr::Overflow));
+    assert_eq!(int(&format!("{max}\npush 2\nmulc"), OverflowMode::Wrap), Err(ExecErr::Overflow));
+    assert_eq!(int(&format!("{min}\npush -1\ndivc"), OverflowMode::Wrap), Err(ExecErr::Overflow));
+    assert_eq!(int("push 1\npush 64\nshlc", OverflowMode::Wrap), Err(ExecErr::Overflow));
+    assert_eq!(int("push 1\npush -1\nshrc", OverflowMode::Wrap), Err(ExecErr::Overflow));
+    assert_eq!(int("push 6\npush 7\nmulc", OverflowMode::Wrap), Ok(42));
+    assert_eq!(int("push 1\npush 0\ndivc", OverflowMode::Wrap), Err(ExecErr::DivByZero));
+
+    assert_eq!(int(&format!("{min}\npush 1\nsubs"), OverflowMode::Wrap), Ok(isize::MIN));
+    assert_eq!(int(&format!("{min}\npush -1\ndivs"), OverflowMode::Wrap), Ok(isize::MAX));
+    assert_eq!(int("push 3\npush 62\nshls", OverflowMode::Wrap), Ok(isize::MAX));
+    assert_eq!(int("push -3\npush 100\nshls", OverflowMode::Wrap), Ok(isize::MIN));
+    assert_eq!(int("push -3\npush 100\nshrs", OverflowMode::Wrap), Ok(0));
+    assert_eq!(int("push 3\npush 2\nadds", OverflowMode::Wrap), Ok(5));
+}
+
+#[test]
+fn check_unsigned_and_remainder() {
+    let int = |source: &str| {
+        let (vm, res) = run(&format!("{source}\nhalt"));
+        res.map(|_| vm.get_stack_top(1)[0])
+    };
+    assert_eq!(int("push -7\npush 2\nrem"), Ok(-1));
+    assert_eq!(int("push 7\npush -2\nrem"), Ok(1));
+    assert_eq!(int("push -7\npush 2\nmod"), Ok(1));
+    assert_eq!(int("push 7\npush -2\nmod"), Ok(-1));
+    assert_eq!(int("push -9223372036854775808\npush -1\nmod"), Ok(0));
+    assert_eq!(int("push 7\npush 0\nmod"), Err(ExecErr::DivByZero));
+    assert_eq!(int("push -1\npush 2\ndivu"), Ok(isize::MAX));
+    assert_eq!(int("push -1\npush 10\nmodu"), Ok(5));
+    assert_eq!(int("push -1\npush 1\nltu"), Ok(0));
+    assert_eq!(int("push -1\npush 1\ngtu"), Ok(1));
+    assert_eq!(int("push -16\npush 2\nshr"), Ok((-16isize as usize >> 2) as isize));
+    assert_eq!(int("push -16\npush 2\nsar"), Ok(-4));
+    assert_eq!(int("push 0xff\nsext8"), Ok(-1));
+    assert_eq!(int("push 0x8000\nsext16"), Ok(-32768));
+    assert_eq!(int("push 0x180000000\nsext32"), Ok(i32::MIN as isize));
+    assert_eq!(int("push -1\nzext8"), Ok(0xff));
+    assert_eq!(int("push -1\nzext16"), Ok(0xffff));
+    assert_eq!(int("push -1\nzext32"), Ok(0xffffffff));
+}
+
+#[test]
+fn check_extended_mem_access() {
+    let stack = |source: &str, n: usize| {
+        let (vm, res) = run(&format!("@buf [8]\n{source}\nhalt"));
+        assert_eq!(res, Ok(()));
+        vm.get_stack_top(n).to_vec()
+    };
+    assert_eq!(stack("push -2\npush @buf\nwrite32\npush @buf\nread32s\npush @buf\nread32", 2), [-2, 0xfffffffe]);
+    assert_eq!(stack("push -3\npush @buf\nwrite8\npush @buf\nread8s", 1), [-3]);
+    assert_eq!(stack("push -300\npush @buf\nwrite16\npush @buf\nread16s", 1), [-300]);
+
+    // bytes in memory don't depend on the host
+    assert_eq!(stack("push 0x0102\npush @buf\nwrite16be\npush @buf\nread8\npush @buf\nread16le", 2), [1, 0x0201]);
+    assert_eq!(stack("push 0x01020304\npush @buf\nwrite32le\npush @buf\nread8\npush @buf\nread32be", 2), [4, 0x04030201]);
+    assert_eq!(stack("push 0x0102030405060708\npush @buf\nwrite64be\npush @buf\nread64be\npush @buf\nread64le", 2),
+        [0x0102030405060708, 0x0807060504030201]);
+    assert_eq!(stack("push 0x0102\npush @buf\nwrite16le\npush @buf\nread16be\npush @buf\nread32le", 2), [0x0201, 0x0102]);
+    assert_eq!(stack("push 0x01020304\npush @buf\nwrite32be\npush @buf\nread32le", 1), [0x04030201]);
+    assert_eq!(stack("push 0x0102030405060708\npush @buf\nwrite64le\npush @buf\nread8", 1), [8]);
+
+    // f32 in memory, f64 on the stack
+    let v = stack("push 1.5\npush @buf\nwritef32\npush @buf\nreadf32\npush @buf\nread32", 2);
+    assert_eq!(f64::from_bits(v[0] as u64), 1.5);
+    assert_eq!(v[1], 1.5f32.to_bits() as isize);
+}
+
+#[test]
+fn check_indirect_jumps() {
+    let top = |source: &str| {
+        let (vm, res) = run(source);
+        res.map(|_| vm.get_stack_top(1)[0])
+    };
+    assert_eq!(top("push &two\njmps\none:\npush 1\nhalt\ntwo:\npush 2\nhalt"), Ok(2));
+    assert_eq!(top("push 1\npush &two\njifs\npush 1\nhalt\ntwo:\npush 2\nhalt"), Ok(2));
+    assert_eq!(top("push 0\npush &two\njifs\npush 1\nhalt\ntwo:\npush 2\nhalt"), Ok(1));
+    assert_eq!(top("push 100\njmps"), Err(ExecErr::IllegalInstAddr));
+    assert_eq!(top("push -1\njmps"), Err(ExecErr::IllegalInstAddr));
+    assert_eq!(file::asm_parse("push &nowhere\nhalt").map(|_| ()), Err((ExecErr::IllegalAddr, 1)));
+
+    // function pointer stored in memory
+    assert_eq!(top("@fn [8]\npush &f\npush @fn\nwrite64\npush @fn\nread64\njmps\nhalt\nf:\npush 7\nhalt"), Ok(7));
+
+    let table = "jtable a b c\npush -1\nhalt\na:\npush 10\nhalt\nb:\npush 11\nhalt\nc:\npush 12\nhalt";
+    assert_eq!(top(&format!("push 0\n{table}")), Ok(10));
+    assert_eq!(top(&format!("push 2\n{table}")), Ok(12));
+    assert_eq!(top(&format!("push 3\n{table}")), Ok(-1));
+    assert_eq!(top(&format!("push -1\n{table}")), Ok(-1));
+    // labels after a table still get the right address
+    assert_eq!(top(&format!("push 1\n{table}")), Ok(11));
+}
+
+#[test]
+fn check_frames() {
+    let top = |source: &str| {
+        let (vm, res) = run(source);
+        res.map(|_| vm.get_stack_top(vm.stack_size.min(3)).to_vec())
+    };
+    // lerp(a, b, t) with the result stored over the first argument
+    let lerp = "jmp main
+lerp:
+    enter 1
+    aload 1
+    aload 2
+    sub
+    aload 0
+    mult
+    lstore 0
+    lload 0
+    aload 2
+    add
+    astore 2
+    leave
+    ret
+main:
+    push 10
+    push 20
+    push 3
+    push $
+    jmp lerp
+    pop
+    pop
+    halt";
+    assert_eq!(top(lerp), Ok(vec![40]));
+
+    let (vm, res) = run("push 1\nenter 2\npush 5\nlstore 1\npush 9\nenter 0\nhalt");
+    assert_eq!(res, Ok(()));
+    assert_eq!(vm.frames(), [6, 2]);
+    assert_eq!(vm.frames_str(&PrintType::I64), "6: [] <- 2: [0, 5, 9]");
+
+    assert_eq!(top("push 1\nenter 2\nlload 1\nleave\nhalt"), Ok(vec![1]));
+    assert_eq!(top("lload 0"), Err(ExecErr::BadFrame));
+    assert_eq!(top("leave"), Err(ExecErr::BadFrame));
+    assert_eq!(top("enter 1\nlload 1"), Err(ExecErr::BadFrame));
+    assert_eq!(top("enter 0\naload 0"), Err(ExecErr::BadFrame));
+    assert_eq!(top("enter 1\nlstore 0"), Err(ExecErr::BadFrame));
+    assert_eq!(top("enter 1\npush 7\nlstore 0\nlload 0\nhalt"), Ok(vec![0, 7, 7]));
+    assert_eq!(top("enter 0\npush 5\npush 1\nshove\nleave"), Err(ExecErr::BadFrame));
+    assert_eq!(top("enter 40"), Err(ExecErr::StackOverflow));
+
+    // the assembler can't express negative slots, raw bytecode can
+    for kind in [InstType::ALOAD, InstType::ASTORE, InstType::LLOAD, InstType::LSTORE] {
+        let mut program = file::asm_parse("push 1\npush 2\nenter 1\npush 3\nhalt").unwrap();
+        program.inst[3] = Inst { kind, has_op: true, operand: -2 };
+        let (_, res) = run_vm(Lada::init(program, 32, 0));
+        assert_eq!(res, Err(ExecErr::BadFrame));
+    }
+}
+
+#[test]
+fn check_try() {
+    let top = |source: &str| {
+        let (vm, res) = run(source);
+        res.map(|_| vm.get_stack_top(vm.stack_size.min(3)).to_vec())
+    };
+    // the stack is unwound to the depth at try
+    assert_eq!(top("push 1\ntry err\npush 2\npush 0\ndiv\nendtry\nhalt\nerr:\nhalt"), Ok(vec![1, ExecErr::DivByZero.code()]));
+    assert_eq!(top("try err\npush 42\nthrow\nhalt\nerr:\nhalt"), Ok(vec![42]));
+    assert_eq!(top("try err\nendtry\npush 42\nthrow\nerr:\nhalt"), Err(ExecErr::Thrown(42)));
+    assert_eq!(top("endtry"), Err(ExecErr::NoHandler));
+    assert_eq!(top("push 5\ntry err\npop\npop\nhalt\nerr:\nhalt"), Ok(vec![ExecErr::StackUnderflow.code()]));
+
+    // nested handlers, the inner one rethrows
+    let nested = "try outer
+    try inner
+    push 7
+    throw
+inner:
+    push 1
+    add
+    throw
+outer:
+    halt";
+    assert_eq!(top(nested), Ok(vec![8]));
+
+    // frames entered inside of the try are left
+    let (vm, res) = run("try err\nenter 2\nlload 5\nerr:\nhalt");
+    assert_eq!(res, Ok(()));
+    assert_eq!((vm.fp(), vm.get_stack_top(1)[0]), (0, ExecErr::BadFrame.code()));
+    assert!(vm.handlers().is_empty());
+
+    // resource limits aren't catchable
+    let mut vm = Lada::init(file::asm_parse("try err\npush 1\nshout\nhalt\nerr:\nhalt").unwrap(), 32, 0);
+    vm.set_limits(Limits { max_output: 0, ..Limits::default() }).unwrap();
+    let (_, res) = run_vm(vm);
+    assert_eq!(res, Err(ExecErr::ResourceLimit(LimitKind::Output)));
+}
+
+#[test]
+fn check_error_codes() {
+    // handlers and plugins see these, they can't change
+    let errs = [
+        (ExecErr::StackOverflow, -1), (ExecErr::StackUnderflow, -2), (ExecErr::IllegalInst, -3),
+        (ExecErr::DivByZero, -4), (ExecErr::NoOperand, -5), (ExecErr::IllegalAddr, -6),
+        (ExecErr::IllegalInstAddr, -7), (ExecErr::IllegalOperand, -8), (ExecErr::Redefinition, -9),
+        (ExecErr::IllegalMemAccess, -10), (ExecErr::WriteToReadOnly, -11), (ExecErr::NativeError, -12),
+        (ExecErr::ResourceLimit(LimitKind::Output), -13), (ExecErr::UninitRead, -14), (ExecErr::Overflow, -15),
+        (ExecErr::BadFrame, -17), (ExecErr::NoHandler, -18), (ExecErr::MissingNative("f".into()), -19),
+        (ExecErr::PermissionDenied("f".into(), "fs".into()), -20), (ExecErr::HostIo, -21),
+        (ExecErr::Thrown(42), 42),
+    ];
+    for (err, code) in errs {
+        assert_eq!(err.code(), code, "{err:?}");
+    }
+}
+
+#[test]
+fn check_stack_words() {
+    let stack = |source: &str| {
+        let (vm, res) = run(&format!("push 1\npush 2\npush 3\npush 4\n{source}\nhalt"));
+        res.map(|_| vm.get_stack_top(vm.stack_size).to_vec())
+    };
+    assert_eq!(stack("over"), Ok(vec![1, 2, 3, 4, 3]));
+    assert_eq!(stack("rot"), Ok(vec![1, 3, 4, 2]));
+    assert_eq!(stack("-rot"), Ok(vec![1, 4, 2, 3]));
+    assert_eq!(stack("nip"), Ok(vec![1, 2, 4]));
+    assert_eq!(stack("tuck"), Ok(vec![1, 2, 4, 3, 4]));
+    assert_eq!(stack("2dup"), Ok(vec![1, 2, 3, 4, 3, 4]));
+    assert_eq!(stack("2drop"), Ok(vec![1, 2]));
+    assert_eq!(stack("2swap"), Ok(vec![3, 4, 1, 2]));
+    assert_eq!(stack("depth"), Ok(vec![1, 2, 3, 4, 4]));
+    assert_eq!(stack("drop 3"), Ok(vec![1]));
+    assert_eq!(stack("drop 0"), Ok(vec![1, 2, 3, 4]));
+    assert_eq!(stack("drop"), Ok(vec![1, 2, 3]));
+    assert_eq!(stack("ifempty"), Ok(vec![1, 2, 3, 4, 0]));
+    assert_eq!(stack("drop 4\nifempty"), Ok(vec![1]));
+    assert_eq!(stack("drop 4\ndepth"), Ok(vec![0]));
+
+    assert_eq!(stack("drop 5"), Err(ExecErr::StackUnderflow));
+    assert_eq!(stack("drop 3\nover"), Err(ExecErr::StackUnderflow));
+    assert_eq!(stack("drop 2\nrot"), Err(ExecErr::StackUnderflow));
+    assert_eq!(stack("2drop\n2swap"), Err(ExecErr::StackUnderflow));
+    assert_eq!(stack("drop 4\nswap"), Err(ExecErr::StackUnderflow));
+    assert_eq!(stack("push 3\nswap"), Ok(vec![4, 2, 3, 1]));
+    assert_eq!(stack("push 4\nswap"), Err(ExecErr::IllegalAddr));
+    assert_eq!(stack("drop 3\npush 1\nswap"), Err(ExecErr::IllegalAddr));
+    assert_eq!(stack("drop 4\npick"), Err(ExecErr::StackUnderflow));
+    assert_eq!(stack("drop 4\nshove"), Err(ExecErr::StackUnderflow));
+    assert!(file::asm_parse("drop x").is_err());
+
+    let mut vm = Lada::init(file::asm_parse("push 1\npush 2\n2dup").unwrap(), 3, 0);
+    vm.exec_inst(&PrintType::I64).unwrap();
+    vm.exec_inst(&PrintType::I64).unwrap();
+    assert_eq!(vm.exec_inst(&PrintType::I64), Err(ExecErr::StackOverflow));
+}
+
+#[test]
+fn check_immediates() {
+    let top = |source: &str| {
+        let (vm, res) = run(&format!("@buf [8]\n{source}\nhalt"));
+        res.map(|_| vm.get_stack_top(vm.stack_size.min(2)).to_vec())
+    };
+    assert_eq!(top("push 5\nadd 3"), Ok(vec![8]));
+    assert_eq!(top("push 5\naddi 3"), Ok(vec![8]));
+    assert_eq!(top("push 5\nsub 3\nmult 4\ndiv 2"), Ok(vec![4]));
+    assert_eq!(top("push 3\nshl 4\nshr 1\nsar 1"), Ok(vec![12]));
+    assert_eq!(top("push 0xf0\nand 0x3c\nor 1\nxor 0xff"), Ok(vec![0xce]));
+    assert_eq!(top("push 5\neq 5\npush 5\nlt 5"), Ok(vec![1, 0]));
+    assert_eq!(top("push 5\ngt 4"), Ok(vec![1]));
+    // same depths as push n and the stack form
+    assert_eq!(top("push 1\npush 2\npush 3\npick 2"), Ok(vec![3, 2]));
+    assert_eq!(top("push 1\npush 2\npush 3\nswap 2"), Ok(vec![2, 1]));
+    assert_eq!(top("push 1\npush 2\npush 9\nshove 1"), Ok(vec![1, 9]));
+    assert_eq!(top("push 42\nwrite64 @buf\nread64 @buf"), Ok(vec![42]));
+    assert_eq!(top("push 0x1234\nwrite16 @buf\nread8 @buf"), Ok(vec![0x34]));
+    assert_eq!(top("push 5\ndiv 0"), Err(ExecErr::DivByZero));
+    assert_eq!(top("push 5\nswap 1"), Err(ExecErr::IllegalAddr));
+    assert_eq!(file::asm_parse("addi").map(|_| ()), Err((ExecErr::NoOperand, 1)));
+    assert_eq!(file::asm_parse("add nothing").map(|_| ()), Err((ExecErr::IllegalOperand, 1)));
+
+    let mut vm = Lada::init(file::asm_parse("push 1\nadd 1\nhalt").unwrap(), 32, 0);
+    vm.set_overflow_mode(OverflowMode::Check);
+    vm.exec_inst(&PrintType::I64).unwrap();
+    vm.exec_inst(&PrintType::I64).unwrap();
+    assert_eq!(vm.get_stack_top(1), [2]);
+
+    // a failed immediate read leaves the stack as it was
+    let (vm, res) = run("push 7\nread64 1000\nhalt");
+    assert_eq!((res, vm.get_stack_top(1)), (Err(ExecErr::IllegalMemAccess), &[7][..]));
+
+    // every instruction survives the bytecode and the disassembler
+    for (i, info) in INST_TABLE.iter().enumerate() {
+        assert_eq!(info.kind as usize, i);
+        assert_eq!(InstType::from_byte(i as u8), Some(info.kind));
+    }
+    assert_eq!(InstType::from_byte(INST_TABLE.len() as u8), None);
+    let prog = file::asm_parse("@buf [8]\npush 1\nadd 2\nread64 @buf\nswap 1\n-rot\n2dup\nreturn:\njmp return").unwrap();
+    let source: Vec<String> = prog.inst.iter().map(|i| i.to_asm()).collect();
+    let again = file::asm_parse(&source.join("\n")).unwrap();
+    assert_eq!(prog.inst.iter().map(|i| (i.kind, i.operand)).collect::<Vec<_>>(),
+               again.inst.iter().map(|i| (i.kind, i.operand)).collect::<Vec<_>>());
+}
+
+#[test]
+fn check_bit_ops() {
+    let int = |source: &str| {
+        let (vm, res) = run(&format!("{source}\nhalt"));
+        res.map(|_| vm.get_stack_top(1)[0])
+    };
+    assert_eq!(int("push 0xf0f0\npopcnt"), Ok(8));
+    assert_eq!(int("push -1\npopcnt"), Ok(64));
+    assert_eq!(int("push 1\nclz"), Ok(63));
+    assert_eq!(int("push 0\nclz"), Ok(64));
+    assert_eq!(int("push 8\nctz"), Ok(3));
+    assert_eq!(int("push 0\nctz"), Ok(64));
+    assert_eq!(int("push 1\npush 63\nrotl"), Ok(isize::MIN));
+    assert_eq!(int("push 1\npush 65\nrotl"), Ok(2));
+    assert_eq!(int("push 1\npush 1\nrotr"), Ok(isize::MIN));
+    assert_eq!(int("push 1\npush -1\nrotl"), Ok(isize::MIN));
+    assert_eq!(int("push 0x0102030405060708\nbswap"), Ok(0x0807060504030201));
+    assert_eq!(int("push 5\npush 2\nbtest"), Ok(1));
+    assert_eq!(int("push 5\npush 1\nbtest"), Ok(0));
+    assert_eq!(int("push 5\npush 1\nbset"), Ok(7));
+    assert_eq!(int("push 0\npush 63\nbset"), Ok(isize::MIN));
+    assert_eq!(int("push 7\npush 0\nbclr"), Ok(6));
+    assert_eq!(int("push 5\npush 64\nbtest"), Err(ExecErr::IllegalOperand));
+    assert_eq!(int("push 5\npush -1\nbset"), Err(ExecErr::IllegalOperand));
+    assert_eq!(int("push 0xabcd\npush 4\npush 8\nbextr"), Ok(0xbc));
+    assert_eq!(int("push -1\npush 0\npush 64\nbextr"), Ok(-1));
+    assert_eq!(int("push -1\npush 60\npush 0\nbextr"), Ok(0));
+    assert_eq!(int("push -1\npush 64\npush 0\nbextr"), Ok(0));
+    assert_eq!(int("push -1\npush 60\npush 8\nbextr"), Err(ExecErr::IllegalOperand));
+    assert_eq!(int("push 0\npush 9223372036854775807\npush 1\nbextr"), Err(ExecErr::IllegalOperand));
+    assert_eq!(int("push 0\npush 1\npush 9223372036854775807\nbextr"), Err(ExecErr::IllegalOperand));
+    assert_eq!(int("push 1\npush 2\nbextr"), Err(ExecErr::StackUnderflow));
+}
+
+#[test]
+fn check_native_registry() {
+    let source = "push 2\npush 3\nnative add_mul\npush 1\nnative add_mul\nnative depth\nhalt";
+    let prog = file::asm_parse(source).unwrap();
+    assert_eq!(prog.imports, ["add_mul", "depth"]);
+
+    let mut vm = Lada::init(prog.clone(), 32, 0);
+    assert_eq!(vm.link(), Err(ExecErr::MissingNative("add_mul".to_string())));
+    let mut factor = 1;
+    vm.register_native("add_mul", "test", 2, move |ctx| {
+        let (b, a) = (ctx.pop()?, ctx.pop()?);
+        factor *= 2;
+        ctx.push((a+b)*factor)
+    });
+    vm.register_native("depth", "test", 0, |ctx| {
+        let depth = ctx.vm.stack_size as isize;
+        ctx.push(depth)
+    });
+    assert_eq!(vm.link(), Ok(()));
+    let (vm, res) = run_vm(vm);
+    assert_eq!(res, Ok(()));
+    // the closure keeps its state between calls
+    assert_eq!(vm.get_stack_top(2), [44, 1]);
+
+    // arity is checked before the call
+    let mut vm = Lada::init(prog, 32, 0);
+    vm.register_native("add_mul", "test", 3, |_| Ok(()));
+    vm.register_native("depth", "test", 0, |_| Ok(()));
+    vm.exec_inst(&PrintType::I64).unwrap();
+    vm.exec_inst(&PrintType::I64).unwrap();
+    assert_eq!(vm.exec_inst(&PrintType::I64), Err(ExecErr::StackUnderflow));
+
+    // builtins keep their indexes
+    let vm = Lada::init(file::asm_parse("halt").unwrap(), 32, 0);
+    assert_eq!(["sys_print", "str_print", "sleep"].map(|n| vm.natives().get(n)), [Some(0), Some(1), Some(2)]);
+    assert_eq!(run("push 7\nnative").1, Err(ExecErr::NativeError));
+    assert_eq!(run("push -1\nnative").1, Err(ExecErr::NativeError));
+}
+
+#[test]
+fn check_native_memory() {
+    let source = "@msg \"hi\"\n@buf [8]\npush 4\nmalloc\nnative copy\nhalt";
+    let mut vm = Lada::init(file::asm_parse(source).unwrap(), 32, 0);
+    // copies "hi" into the chunk and into @buf
+    vm.register_native("copy", "test", 1, |ctx| {
+        let dest = ctx.pop()?;
+        let s = ctx.read_str(file::GUARD_SIZE as isize, 2)?;
+        ctx.write_bytes(dest, s.as_bytes())?;
+        ctx.write_bytes(file::GUARD_SIZE as isize + 2, s.as_bytes())?;
+        let back = ctx.read_bytes(dest, 2)?;
+        ctx.push(back[1] as isize)?;
+        // out of the chunk and read-only data
+        assert_eq!(ctx.read_bytes(dest, 5), Err(ExecErr::IllegalMemAccess));
+        assert_eq!(ctx.read_bytes(dest, usize::MAX), Err(ExecErr::IllegalMemAccess));
+        assert_eq!(ctx.write_bytes(file::GUARD_SIZE as isize, b"x"), Err(ExecErr::WriteToReadOnly));
+        assert_eq!(ctx.write_bytes(0, b"x"), Err(ExecErr::WriteToReadOnly));
+        assert_eq!(ctx.read_bytes(1000, 1), Err(ExecErr::IllegalMemAccess));
+        assert_eq!(ctx.pop(), Ok(b'i' as isize));
+        assert_eq!(ctx.pop(), Err(ExecErr::StackUnderflow));
+        ctx.push(back[0] as isize)
+    });
+    let (vm, res) = run_vm(vm);
+    assert_eq!(res, Ok(()));
+    assert_eq!(vm.get_stack_top(1), [b'h' as isize]);
+    assert_eq!(vm.get_dyn_mem()[0].as_deref(), Some(&b"hi\0\0"[..]));
+    assert_eq!(&vm.get_arena()[file::GUARD_SIZE+2..file::GUARD_SIZE+4], b"hi");
+
+    // builtins check their arguments instead of panicking
+    assert_eq!(run("push 8\npush 100\nnative str_print").1, Err(ExecErr::IllegalMemAccess));
+    assert_eq!(run("push 8\npush -1\nnative str_print").1, Err(ExecErr::IllegalMemAccess));
+    assert_eq!(run("push 8\nnative str_print").1, Err(ExecErr::StackUnderflow));
+    assert_eq!(run("push -5\nnative sleep\nhalt").1, Ok(()));
+}
+
+#[test]
+fn check_file_natives() {
+    let root = std::env::temp_dir().join(format!("lada_fs_test_{}", std::process::id()));
+    std::fs::create_dir_all(root.join("sub")).unwrap();
+    std::fs::write(root.join("in.txt"), b"hello").unwrap();
+
+    let run_fs = |source: &str, writable: bool| {
+        let mut vm = Lada::init(file::asm_parse(source).unwrap(), 32, 0);
+        files::register(vm.natives_mut(), files::Sandbox::new(&root, writable).unwrap());
+        let (vm, res) = run_vm(vm);
+        res.map(|_| (vm.get_stack_top(vm.stack_size).to_vec(), vm.get_arena().to_vec()))
+    };
+    let open = |path: &str, mode: isize| format!("@path \"{path}\"\npush @path\npush {}\npush {mode}\nnative open", path.len());
+
+    // read the whole file, then seek to 1 and read 3 bytes over the start of the buffer
+    let source = format!("@buf [8]\n{}
+dup\npush @buf\npush 8\nnative read\nswap 1
+dup\npush 1\npush 0\nnative seek\npop
+dup\npush @buf\npush 3\nnative read\nswap 1
+native close\nhalt", open("in.txt", files::READ));
+    let (stack, arena) = run_fs(&source, false).unwrap();
+    assert_eq!(stack, [5, 3, 0]);
+    let buf = file::GUARD_SIZE + "in.txt".len();
+    assert_eq!(&arena[buf..buf+5], b"elllo");
+
+    // a bad destination fails before anything is read from the file
+    let source = format!("@buf [8]\n{}\ntry bad
+dup\npush @path\npush 5\nnative read
+bad:\npop
+dup\npush @buf\npush 8\nnative read\nswap 1
+native close\nhalt", open("in.txt", files::READ));
+    let (stack, arena) = run_fs(&source, false).unwrap();
+    assert_eq!(stack, [5, 0]);
+    assert_eq!(&arena[buf..buf+5], b"hello");
+
+    // write needs write access
+    let write = format!("@data \"data\"\n{}\ndup\npush @data\npush 4\nnative write\nswap 1\nnative close\nhalt", open("sub/out.txt", files::WRITE));
+    assert_eq!(run_fs(&write, false).unwrap().0, [files::BAD_HANDLE, files::BAD_HANDLE]);
+    assert_eq!(run_fs(&write, true).unwrap().0, [4, 0]);
+    assert_eq!(std::fs::read(root.join("sub/out.txt")).unwrap(), b"data");
+    assert_eq!(run_fs(&format!("{}\nhalt", open("sub/out.txt", files::WRITE)), false).unwrap().0, [files::PERMISSION]);
+
+    // nothing outside of the root
+    assert_eq!(run_fs(&format!("{}\nhalt", open("../etc/passwd", files::READ)), true).unwrap().0, [files::PERMISSION]);
+    assert_eq!(run_fs(&format!("{}\nhalt", open("missing", files::READ)), true).unwrap().0, [files::NOT_FOUND]);
+    assert_eq!(run_fs(&format!("{}\nhalt", open("in.txt", 7)), true).unwrap().0, [files::INVALID]);
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink("/etc", root.join("escape")).unwrap();
+        assert_eq!(run_fs(&format!("{}\nhalt", open("escape/passwd", files::READ)), true).unwrap().0, [files::PERMISSION]);
+        // a link to a file that doesn't exist yet isn't created through
+        let outside = std::env::temp_dir().join(format!("lada_fs_outside_{}", std::process::id()));
+        std::os::unix::fs::symlink(&outside, root.join("dangling")).unwrap();
+        assert_eq!(run_fs(&format!("{}\nhalt", open("dangling", files::WRITE)), true).unwrap().0, [files::PERMISSION]);
+        assert!(!outside.exists());
+    }
+
+    let stat = "@path \"in.txt\"\npush @path\npush 6\nnative stat\npush 5\nnative close\nhalt";
+    assert_eq!(run_fs(stat, false).unwrap().0, [5, files::BAD_HANDLE]);
+    // bad pointers are still errors of the vm
+    assert_eq!(run_fs("push 1000\npush 4\nnative stat", false), Err(ExecErr::IllegalMemAccess));
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn check_input_natives() {
+    let run_input = |source: &str, text: &'static str| {
+        let mut vm = Lada::init(file::asm_parse(source).unwrap(), 32, 0);
+        input::register(vm.natives_mut());
+        vm.set_host(Box::new(host::MemHost::new(text.as_bytes())));
+        let (vm, res) = run_vm(vm);
+        res.map(|_| (vm.get_stack_top(vm.stack_size).to_vec(), vm.get_arena().to_vec()))
+    };
+    let lines = "@buf [8]\npush @buf\npush 8\nnative read_line\npush @buf\npush 3\nnative read_line\nnative read_byte\nnative eof\nhalt";
+    let (stack, arena) = run_input(lines, "hi\r\nabcdef").unwrap();
+    assert_eq!(stack, [2, 3, b'd' as isize, 0]);
+    assert_eq!(&arena[file::GUARD_SIZE..file::GUARD_SIZE+3], b"abc");
+
+    let (stack, _) = run_input("@buf [4]\nnative read_byte\nnative eof\npush @buf\npush 4\nnative read_line\nhalt", "x").unwrap();
+    assert_eq!(stack, [b'x' as isize, 1, input::EOF]);
+    // the line is still there after a read into read-only memory failed
+    let retry = "@msg \"hi\"\n@buf [4]\ntry bad\npush @msg\npush 4\nnative read_line\nbad:\npop\npush @buf\npush 4\nnative read_line\nhalt";
+    let (stack, arena) = run_input(retry, "abc\n").unwrap();
+    assert_eq!(stack, [3]);
+    assert_eq!(&arena[file::GUARD_SIZE+2..file::GUARD_SIZE+5], b"abc");
+
+    let numbers = "native read_int\nnative read_int\nnative read_float\nnative read_int\nhalt";
+    let (stack, _) = run_input(numbers, "  12\n-7 2.5").unwrap();
+    assert_eq!(stack, [12, 0, -7, 0, 2.5f64.to_bits() as isize, 0, 0, input::EOF]);
+    let (stack, _) = run_input("native read_int\nnative read_int\nhalt", "abc 5").unwrap();
+    assert_eq!(stack, [0, files::INVALID, 5, 0]);
+}
+
+#[test]
+fn check_clock_and_random() {
+    let (vm, res) = run("native clock_mono\nnative clock_mono\nnative clock_wall\nhalt");
+    assert_eq!(res, Ok(()));
+    let t = vm.get_stack_top(3);
+    assert!(0 <= t[0] && t[0] <= t[1]);
+    // after 2020
+    assert!(t[2] > 1_577_836_800_000_000_000);
+
+    let run_rand = |seed: u64| {
+        let mut vm = Lada::init(file::asm_parse("native rand_int\npush -3\npush 4\nnative rand_range\nnative rand_float\nhalt").unwrap(), 32, 0);
+        random::register(vm.natives_mut(), seed);
+        let (vm, res) = run_vm(vm);
+        res.map(|_| vm.get_stack_top(3).to_vec())
+    };
+    assert_eq!(run_rand(42), run_rand(42));
+    assert_ne!(run_rand(42), run_rand(43));
+    for seed in 0..50 {
+        let v = run_rand(seed).unwrap();
+        assert!((-3..4).contains(&v[1]));
+        assert!((0.0..1.0).contains(&f64::from_bits(v[2] as u64)));
+    }
+
+    let mut rng = random::Rng::new(7);
+    assert_eq!(rng.range(5, 5), None);
+    assert!((0..1000).all(|_| rng.range(isize::MIN, isize::MAX).is_some()));
+    let mut seen = [false; 6];
+    for _ in 0..1000 { seen[rng.range(10, 16).unwrap() as usize - 10] = true; }
+    assert!(seen.iter().all(|s| *s));
+    // reference values of xoshiro256** with splitmix64 seeding
+    assert_eq!(random::Rng::new(0).next_u64(), 0x99ec5f36cb75f2b4);
+}
+
+#[test]
+fn check_permissions() {
+    let policy = native::Policy::parse("# a comment\nio.fs\n  time  # clocks and sleep\n\n");
+    assert_eq!(policy.grants(), ["io.fs", "time"]);
+    assert!(policy.allows("io.fs.read") && policy.allows("io.fs.write") && policy.allows("time"));
+    assert!(!policy.allows("io.stdout") && !policy.allows("io") && !policy.allows("io.fsx") && !policy.allows("timer"));
+    assert!(native::Policy::allow_all().allows("anything"));
+    assert!(!native::Policy::deny_all().allows("time"));
+
+    let run_with = |source: &str, policy: native::Policy| {
+        let mut vm = Lada::init(file::asm_parse(source).unwrap(), 32, 0);
+        vm.set_policy(policy);
+        run_vm(vm).1
+    };
+    let denied = |native: &str, cap: &str| Err(ExecErr::PermissionDenied(native.to_string(), cap.to_string()));
+    // linking doesn't need permissions, the call does
+    assert_eq!(run_with("native clock_wall\nhalt", native::Policy::deny_all()), denied("clock_wall", native::TIME));
+    assert_eq!(run_with("push 0\nnative\nhalt", native::Policy::parse("time")), denied("sys_print", native::STDOUT));
+    assert_eq!(run_with("native clock_wall\nhalt", native::Policy::parse("time")), Ok(()));
+    // a denied permission can't be caught
+    assert_eq!(run_with("try fail\nnative clock_mono\nendtry\nfail:\nhalt", native::Policy::deny_all()),
+               denied("clock_mono", native::TIME));
+
+    // opening for writing needs io.fs.write on top of the declared io.fs.read
+    let root = std::env::temp_dir().join(format!("lv_permissions_{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    let open = |mode: isize, policy: &str| {
+        let source = format!("@name \"f\"\npush @name\npush 1\npush {mode}\nnative open\nhalt");
+        let mut vm = Lada::init(file::asm_parse(&source).unwrap(), 32, 0);
+        files::register(vm.natives_mut(), files::Sandbox::new(&root, true).unwrap());
+        vm.set_policy(native::Policy::parse(policy));
+        run_vm(vm).1
+    };
+    assert_eq!(open(files::WRITE, "io.fs.read"), denied("open", native::FS_WRITE));
+    assert_eq!(open(files::WRITE, "io.fs"), Ok(()));
+    assert_eq!(open(files::READ, "io.fs.read"), Ok(()));
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn check_plugins() {
+    let dir = std::env::temp_dir().join(format!("lv_plugin_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let lib = dir.join("libstrings.so");
+    let status = std::process::Command::new(std::env::var("RUSTC").unwrap_or("rustc".to_string()))
+        .args(["--edition", "2021", "--crate-type", "cdylib", concat!(env!("CARGO_MANIFEST_DIR"), "/src/examples/plugin.rs"), "-o"])
+        .arg(&lib)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let run_plugin = |source: &str, policy: native::Policy| {
+        let mut vm = Lada::init(file::asm_parse(source).unwrap(), 32, 0);
+        assert_eq!(plugin::load(vm.natives_mut(), &lib).unwrap(), ["str_reverse", "str_count"]);
+        vm.set_policy(policy);
+        run_vm(vm)
+    };
+    let (vm, res) = run_plugin("@buf 0x6f6c6c6568\npush @buf\npush 5\nnative str_reverse\npush @buf\npush 5\npush 108\nnative str_count\nhalt",
+                               native::Policy::parse("strings"));
+    assert_eq!(res, Ok(()));
+    assert_eq!(&vm.get_arena()[file::GUARD_SIZE..file::GUARD_SIZE+5], b"olleh");
+    assert_eq!(vm.get_stack_top(1), [2]);
+
+    // errors of api calls reach the program like the errors of other natives
+    let (_, res) = run_plugin("@msg \"hi\"\npush @msg\npush 2\nnative str_reverse\nhalt", native::Policy::allow_all());
+    assert_eq!(res, Err(ExecErr::WriteToReadOnly));
+    let (_, res) = run_plugin("push 8\nnative str_reverse\nhalt", native::Policy::allow_all());
+    assert_eq!(res, Err(ExecErr::StackUnderflow));
+    let (_, res) = run_plugin("push 8\npush 1000\nnative str_reverse\nhalt", native::Policy::allow_all());
+    assert_eq!(res, Err(ExecErr::IllegalMemAccess));
+    let (_, res) = run_plugin("push 8\npush 1\nnative str_reverse\nhalt", native::Policy::deny_all());
+    assert_eq!(res, Err(ExecErr::PermissionDenied("str_reverse".to_string(), "strings".to_string())));
+
+    let mut natives = native::NativeRegistry::new();
+    assert!(plugin::load(&mut natives, &dir.join("missing.so")).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn check_extern_decls() {
+    let ext = ffi::Extern::parse(" memset (ptr, i32 i64) -> ptr").unwrap();
+    assert_eq!(ext.params, [ffi::CType::Ptr, ffi::CType::I32, ffi::CType::I64]);
+    assert_eq!(ffi::Extern::parse(&ext.to_string()["%extern ".len()..]), Some(ext));
+    assert_eq!(ffi::Extern::parse("abort ()").map(|e| e.ret), Some(ffi::CType::Void));
+    let ext = ffi::Extern::parse("puts (cptr) -> i32").unwrap();
+    assert_eq!(ext.params, [ffi::CType::CPtr]);
+    assert_eq!(ffi::Extern::parse(&ext.to_string()["%extern ".len()..]), Some(ext));
+    assert_eq!(ffi::Extern::parse("f (i8) -> i32"), None);
+    assert_eq!(ffi::Extern::parse("f (void) -> i32"), None);
+    assert_eq!(ffi::Extern::parse("f (i32) i32"), None);
+    assert_eq!(ffi::Extern::parse("f (i32, i32, i32, i32, i32, i32, ptr)"), None);
+    assert!(ffi::Extern::parse("f (i32, i32, i32, i32, i32, i32, f64, f64, f64, f64, f64, f64, f64, f64)").is_some());
+    assert_eq!(file::asm_parse("%extern f (i8)").err(), Some((ExecErr::IllegalOperand, 1)));
+    assert_eq!(file::asm_parse("%extern f ()\n%extern f (i32)").err(), Some((ExecErr::Redefinition, 2)));
+}
+
+#[cfg(all(unix, target_arch = "x86_64"))]
+#[test]
+fn check_extern_calls() {
+    let run_ffi = |source: &str, policy: native::Policy| {
+        let mut vm = Lada::init(file::asm_parse(source).unwrap(), 32, 0);
+        let externs = vm.externs().to_vec();
+        ffi::register(vm.natives_mut(), &externs, &[]).unwrap();
+        vm.set_policy(policy);
+        run_vm(vm)
+    };
+    let source = "%extern labs (i64) -> i64
+%extern strlen (cptr) -> i64
+%extern memset (ptr, i32, i64) -> ptr
+%extern ldexp (f64, i32) -> f64
+%extern fmaxf (f32, f32) -> f32
+@msg \"hello\\0\"
+push -7
+native labs
+push @msg
+native strlen
+push 8
+malloc
+push 120
+push 4
+native memset
+pop
+push 1.5
+push 3
+native ldexp
+push 2.5
+push -1.0
+native fmaxf
+halt";
+    let (vm, res) = run_ffi(source, native::Policy::parse("ffi"));
+    assert_eq!(res, Ok(()));
+    let top = vm.get_stack_top(4);
+    assert_eq!(top[..2], [7, 5]);
+    assert_eq!(f64::from_bits(top[2] as u64), 12.0);
+    assert_eq!(f64::from_bits(top[3] as u64), 2.5);
+    // the chunk was written through its host pointer
+    assert_eq!(vm.get_dyn_mem()[0].as_deref(), Some(&b"xxxx\0\0\0\0"[..]));
+
+    let (_, res) = run_ffi("%extern labs (i64) -> i64\npush 1\nnative labs\nhalt", native::Policy::deny_all());
+    assert_eq!(res, Err(ExecErr::PermissionDenied("labs".to_string(), native::FFI.to_string())));
+    // pointers outside of vm memory aren't passed on
+    let (_, res) = run_ffi("%extern strlen (cptr) -> i64\npush 100000\nnative strlen\nhalt", native::Policy::allow_all());
+    assert_eq!(res, Err(ExecErr::IllegalMemAccess));
+    // foreign code can't write into read-only data
+    let (vm, res) = run_ffi("%extern memset (ptr, i32, i64) -> ptr\n@ro \"abcdefgh\"\npush @ro\npush 65\npush 8\nnative memset\nhalt",
+                            native::Policy::allow_all());
+    assert_eq!(res, Err(ExecErr::WriteToReadOnly));
+    assert_eq!(&vm.get_arena()[file::GUARD_SIZE..file::GUARD_SIZE+8], b"abcdefgh");
+
+    let mut natives = native::NativeRegistry::new();
+    let missing = [ffi::Extern::parse("lv_no_such_function ()").unwrap()];
+    assert!(ffi::register(&mut natives, &missing, &[]).is_err());
+}
+
+#[test]
+fn check_host() {
+    let source = "@msg \"hi\"\npush 5\nprint\npush @msg\npush 2\nnative str_print\npush 7\nshout\ndump\nhalt";
+    let mut vm = Lada::init(file::asm_parse(source).unwrap(), 32, 0);
+    let host = host::MemHost::new(b"");
+    let (out, err) = (host.out.clone(), host.err.clone());
+    vm.set_host(Box::new(host));
+    let (mut vm, res) = run_vm(vm);
+    assert_eq!(res, Ok(()));
+    assert_eq!(out.text(), "5 | 5 | 2.4703282e-323\nhi\n7 | 7 | 3.4584595e-323\nStack: [5]\n");
+
+    // formatting the vm doesn't print anything
+    out.clear();
+    let debug = format!("{vm:?}");
+    assert!(debug.contains("stack used: [5]"));
+    assert_eq!(out.text(), "");
+    vm.print_stack(&PrintType::I64);
+    assert_eq!(out.text(), "[5]\n");
+
+    // invalid utf-8 is reported on the host's stderr
+    let mut vm = Lada::init(file::asm_parse("@bad 255\npush @bad\npush 1\nnative str_print\nhalt").unwrap(), 32, 0);
+    let host = host::MemHost::new(b"");
+    let err_out = host.err.clone();
+    vm.set_host(Box::new(host));
+    let (_, res) = run_vm(vm);
+    assert_eq!(res, Err(ExecErr::NativeError));
+    assert!(err_out.text().contains("invalid utf-8"));
+    assert_eq!(err.text(), "");
+
+    // time only moves when the program sleeps
+    let mut vm = Lada::init(file::asm_parse("native clock_mono\npush 3\nnative sleep\nnative clock_mono\nnative clock_wall\nhalt").unwrap(), 32, 0);
+    let mut host = host::MemHost::new(b"");
+    host.now_ns = 1000;
+    vm.set_host(Box::new(host));
+    let (vm, res) = run_vm(vm);
+    assert_eq!(res, Ok(()));
+    assert_eq!(vm.get_stack_top(3), [1000, 3_001_000, 3_001_000]);
+}
+
+#[test]
+fn check_args_and_env() {
+    let mut vm = Lada::init(file::asm_parse("@x 1\nhalt").unwrap(), 32, 0);
+    vm.set_sanitizer(Some(sanitizer::SanitizeMode::Error));
+    let args = ["prog.lb".to_string(), "".to_string(), "two words".to_string()];
+    vm.set_args(&args).unwrap();
+    let [argc, argv] = vm.get_stack_top(2).try_into().unwrap();
+    assert_eq!(argc, 3);
+    let arena = vm.get_arena();
+    let ptr = |i: usize| usize::from_ne_bytes(arena[argv as usize+i*8..argv as usize+i*8+8].try_into().unwrap());
+    for (i, arg) in args.iter().enumerate() {
+        assert_eq!(&arena[ptr(i)..ptr(i)+arg.len()+1], [arg.as_bytes(), b"\0"].concat());
+    }
+    assert_eq!(ptr(3), 0);
+    assert_eq!(argv as usize % 8, 0);
+    // the arguments count as initialized
+    assert!(vm.check_init(ptr(2) as isize, 9).is_ok());
+
+    let mut vm = Lada::init(file::asm_parse("halt").unwrap(), 32, 0);
+    vm.set_limits(Limits { max_arena: 32, ..Limits::default() }).unwrap();
+    assert_eq!(vm.set_args(&["a long argument that doesn't fit".to_string()]), Err(ExecErr::ResourceLimit(LimitKind::Arena)));
+    let mut vm = Lada::init(file::asm_parse("halt").unwrap(), 1, 0);
+    assert_eq!(vm.set_args(&[]), Err(ExecErr::StackOverflow));
+
+    let run_env = |source: &str, policy: native::Policy| {
+        let mut vm = Lada::init(file::asm_parse(source).unwrap(), 32, 0);
+        let mut host = host::MemHost::new(b"");
+        host.env.insert("LV_TEST".to_string(), "value".to_string());
+        vm.set_host(Box::new(host));
+        vm.set_policy(policy);
+        let (vm, res) = run_vm(vm);
+        res.map(|_| (vm.get_stack_top(vm.stack_size).to_vec(), vm.get_arena().to_vec()))
+    };
+    let getenv = |name: &str, max: usize| format!("@name \"{name}\"\n@buf [8]\npush @name\npush {}\npush @buf\npush {max}\nnative getenv\nhalt", name.len());
+    // @buf comes right after the name in read-only data
+    let buf = file::GUARD_SIZE + "LV_TEST".len();
+    let (stack, arena) = run_env(&getenv("LV_TEST", 8), native::Policy::parse("process")).unwrap();
+    assert_eq!(stack, [5]);
+    assert_eq!(&arena[buf..buf+5], b"value");
+    // the full length tells the program the buffer was too small
+    let (stack, arena) = run_env(&getenv("LV_TEST", 3), native::Policy::parse("process.env")).unwrap();
+    assert_eq!(stack, [5]);
+    assert_eq!(&arena[buf..buf+4], b"val\0");
+    assert_eq!(run_env(&getenv("LV_MISSING", 8), native::Policy::allow_all()).unwrap().0, [-1]);
+    assert_eq!(run_env(&getenv("LV_TEST", 8), native::Policy::parse("io")),
+               Err(ExecErr::PermissionDenied("getenv".to_string(), native::ENV.to_string())));
+}
@@ -0,0 +1,43 @@
+// watchpoints on memory ranges, checked by read_mem!/write_mem! only when some are registered
+use super::Region;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub region: Region,
+    // offset inside of the region
+    pub start: usize,
+    pub len: usize,
+    pub kind: WatchKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchHit {
+    pub id: usize,
+    pub ip: usize,
+    pub addr: isize,
+    pub write: bool,
+    // for reads both are the value that was read
+    pub old: isize,
+    pub new: isize,
+}
+
+// returns true to halt the vm
+pub type WatchCallback = Box<dyn FnMut(&WatchHit) -> bool>;
+
+impl Watchpoint {
+    pub(crate) fn matches(&self, region: &Region, offset: usize, len: usize, write: bool) -> bool {
+        let kind = match self.kind {
+            WatchKind::Read => !write,
+            WatchKind::Write => write,
+            WatchKind::ReadWrite => true,
+        };
+        kind && self.region == *region && offset < self.start.saturating_add(self.len) && self.start < offset.saturating_add(len)
+    }
+}